@@ -0,0 +1,456 @@
+use super::{collect_stream, peek_stream, start_chat, unmap_model, ChatCollector};
+use crate::error::ProxyError;
+use crate::models::{Message, ModelOptions, Think, ToolCall, ToolCallFunction};
+use crate::providers::{ChatCompletionRequest, ChatEvent, ProviderError, StopReason};
+use crate::AppState;
+use async_stream::stream;
+use axum::{
+    extract::{rejection::JsonRejection, Json, State},
+    http::HeaderMap,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_stream::StreamExt;
+use tracing::{debug, warn};
+
+/// A `ProxyError` answered in Anthropic's error shape
+pub struct AnthropicError(ProxyError);
+
+impl From<ProxyError> for AnthropicError {
+    fn from(e: ProxyError) -> Self {
+        AnthropicError(e)
+    }
+}
+
+impl From<ProviderError> for AnthropicError {
+    fn from(e: ProviderError) -> Self {
+        AnthropicError(ProxyError::from(e))
+    }
+}
+
+impl From<JsonRejection> for AnthropicError {
+    fn from(e: JsonRejection) -> Self {
+        AnthropicError(ProxyError::from(e))
+    }
+}
+
+impl IntoResponse for AnthropicError {
+    fn into_response(self) -> Response {
+        self.0.log();
+        (self.0.status_code(), Json(self.0.to_anthropic_json())).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicRequest {
+    model: String,
+    messages: Vec<AnthropicMessage>,
+    system: Option<Content>,
+    max_tokens: Option<i64>,
+    #[serde(default)]
+    stop_sequences: Vec<String>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    top_k: Option<i64>,
+    #[serde(default)]
+    stream: bool,
+    // client tools have an input_schema, server tools (web search, ...) are not supported
+    #[serde(default)]
+    tools: Vec<Value>,
+    // {"type": "enabled", "budget_tokens": n} or {"type": "disabled"}
+    thinking: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicMessage {
+    role: String,
+    content: Content,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: Option<Content>,
+        #[serde(default)]
+        is_error: bool,
+    },
+    Thinking {
+        thinking: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ImageSource {
+    Base64 {
+        data: String,
+    },
+    Url {
+        url: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl Content {
+    fn into_text(self) -> String {
+        match self {
+            Content::Text(text) => text,
+            Content::Blocks(blocks) => blocks
+                .into_iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl AnthropicRequest {
+    /// The request in the proxy's internal protocol, addressed to `model` as the upstream knows it
+    fn into_chat_request(self, model: String) -> ChatCompletionRequest {
+        let tools = self
+            .tools
+            .into_iter()
+            .filter_map(|tool| {
+                let Some(schema) = tool.get("input_schema") else {
+                    warn!("tool {} is not supported, ignoring it", tool["name"]);
+                    return None;
+                };
+                Some(json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool["name"],
+                        "description": tool["description"].as_str().unwrap_or_default(),
+                        "parameters": schema,
+                    },
+                }))
+            })
+            .collect();
+        let think = self
+            .thinking
+            .as_ref()
+            .and_then(|thinking| thinking["type"].as_str())
+            .map(|kind| Think::Enabled(kind == "enabled"));
+        let mut options = ModelOptions {
+            num_predict: self.max_tokens,
+            stop: (!self.stop_sequences.is_empty()).then_some(self.stop_sequences),
+            top_p: self.top_p,
+            temperature: self.temperature,
+            ..Default::default()
+        };
+        if let Some(top_k) = self.top_k {
+            options.extra.insert("top_k".to_string(), json!(top_k));
+        }
+
+        ChatCompletionRequest {
+            model,
+            messages: to_messages(self.system, self.messages),
+            tools,
+            format: None,
+            think,
+            options: Some(options),
+        }
+    }
+}
+
+/// Translates Anthropic messages into Ollama-style ones. Tool results become `tool` messages of
+/// their own, named after the call they answer
+fn to_messages(system: Option<Content>, messages: Vec<AnthropicMessage>) -> Vec<Message> {
+    let mut out = Vec::new();
+    if let Some(system) = system {
+        out.push(Message::new("system", system.into_text()));
+    }
+    let mut names: HashMap<String, String> = HashMap::new();
+    for m in messages {
+        let blocks = match m.content {
+            Content::Text(text) => {
+                out.push(Message::new(&m.role, text));
+                continue;
+            }
+            Content::Blocks(blocks) => blocks,
+        };
+
+        let mut message = Message::new(&m.role, String::new());
+        let mut texts = Vec::new();
+        let mut images = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in blocks {
+            match block {
+                ContentBlock::Text { text } => texts.push(text),
+                ContentBlock::Image { source } => match source {
+                    ImageSource::Base64 { data } => images.push(data),
+                    ImageSource::Url { url } => images.push(url),
+                    ImageSource::Other => {}
+                },
+                ContentBlock::ToolUse { id, name, input } => {
+                    names.insert(id.clone(), name.clone());
+                    tool_calls.push(ToolCall {
+                        id: Some(id),
+                        function: ToolCallFunction {
+                            index: None,
+                            name,
+                            arguments: input,
+                        },
+                    });
+                }
+                ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                    is_error,
+                } => {
+                    let mut result = content.map(Content::into_text).unwrap_or_default();
+                    if is_error {
+                        result = format!("Error: {}", result);
+                    }
+                    out.push(Message {
+                        tool_name: names.get(&tool_use_id).cloned(),
+                        tool_call_id: Some(tool_use_id),
+                        ..Message::new("tool", result)
+                    });
+                }
+                ContentBlock::Thinking { thinking } => message.thinking = Some(thinking),
+                ContentBlock::Other => {}
+            }
+        }
+        // a user turn made only of tool results has nothing left to send
+        if texts.is_empty() && images.is_empty() && tool_calls.is_empty() {
+            continue;
+        }
+        message.content = texts.join("\n");
+        message.images = (!images.is_empty()).then_some(images);
+        message.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        out.push(message);
+    }
+    out
+}
+
+fn stop_reason(collected: &ChatCollector) -> &'static str {
+    match &collected.done_reason {
+        Some(StopReason::Length) => "max_tokens",
+        Some(StopReason::ToolCalls) => "tool_use",
+        Some(StopReason::ContentFilter) => "refusal",
+        // Ollama upstreams say `stop` after tool calls
+        _ if !collected.tool_calls.is_empty() => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+fn sse(name: &str, data: Value) -> Event {
+    Event::default().event(name).data(data.to_string())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BlockKind {
+    Text,
+    Thinking,
+    // by tool call index
+    ToolUse(usize),
+}
+
+/// Re-encodes streamed content as Anthropic content blocks, opening a new block whenever the kind
+/// of content changes
+#[derive(Default)]
+struct BlockEncoder {
+    // index and kind of the open block
+    open: Option<(usize, BlockKind)>,
+    next_index: usize,
+}
+
+impl BlockEncoder {
+    /// Makes sure a block of `kind` is open, starting it with `block` when it is not
+    fn switch(&mut self, kind: BlockKind, block: impl FnOnce() -> Value) -> Vec<Event> {
+        if matches!(self.open, Some((_, open)) if open == kind) {
+            return Vec::new();
+        }
+        let mut events = self.close();
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open = Some((index, kind));
+        events.push(sse(
+            "content_block_start",
+            json!({ "type": "content_block_start", "index": index, "content_block": block() }),
+        ));
+        events
+    }
+
+    fn delta(&self, delta: Value) -> Event {
+        let index = self.open.map(|(index, _)| index).unwrap_or_default();
+        sse(
+            "content_block_delta",
+            json!({ "type": "content_block_delta", "index": index, "delta": delta }),
+        )
+    }
+
+    fn close(&mut self) -> Vec<Event> {
+        match self.open.take() {
+            Some((index, _)) => vec![sse(
+                "content_block_stop",
+                json!({ "type": "content_block_stop", "index": index }),
+            )],
+            None => Vec::new(),
+        }
+    }
+}
+
+pub async fn handle_messages(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Result<Json<AnthropicRequest>, JsonRejection>,
+) -> Result<Response, AnthropicError> {
+    let Json(payload) = payload?;
+    let start = Instant::now();
+    let (provider, target) = unmap_model(payload.model.clone(), &state.providers).await?;
+
+    // like Anthropic, answer with the model name the client asked for
+    let model = payload.model.clone();
+    let stream_mode = payload.stream;
+    let request = payload.into_chat_request(target.name.clone());
+    let stream = start_chat(&state, provider, &target, request, &headers).await?;
+
+    let id = format!(
+        "msg_{:x}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+
+    if !stream_mode {
+        let collected = collect_stream(stream, start).await?;
+
+        let mut content = Vec::new();
+        if let Some(thinking) = collected.thinking() {
+            content.push(json!({ "type": "thinking", "thinking": thinking, "signature": "" }));
+        }
+        if !collected.content.is_empty() || collected.tool_calls.is_empty() {
+            content.push(json!({ "type": "text", "text": collected.content }));
+        }
+        for (i, call) in collected.tool_calls.tool_calls().into_iter().enumerate() {
+            content.push(json!({
+                "type": "tool_use",
+                "id": call.id.unwrap_or_else(|| format!("toolu_{}", i)),
+                "name": call.function.name,
+                "input": call.function.arguments,
+            }));
+        }
+        let resp = json!({
+            "id": id,
+            "type": "message",
+            "role": "assistant",
+            "model": model,
+            "content": content,
+            "stop_reason": stop_reason(&collected),
+            "stop_sequence": null,
+            "usage": {
+                "input_tokens": collected.metrics.prompt_eval_count,
+                "output_tokens": collected.metrics.eval_count,
+            },
+        });
+
+        debug!("\n>>> messages: {{{}}}", collected.content);
+        Ok(Json(resp).into_response())
+
+    // stream mode
+    } else {
+        let stream = peek_stream(stream).await?;
+        let events = stream! {
+            let mut collector = ChatCollector::new(start);
+            let mut blocks = BlockEncoder::default();
+            let mut s = stream;
+            yield Ok::<_, std::convert::Infallible>(sse("message_start", json!({
+                "type": "message_start",
+                "message": {
+                    "id": id,
+                    "type": "message",
+                    "role": "assistant",
+                    "model": model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": { "input_tokens": 0, "output_tokens": 0 },
+                },
+            })));
+            while let Some(event) = s.next().await {
+                collector.push(&event);
+                let mut out = Vec::new();
+                match event {
+                    ChatEvent::ContentDelta(text) => {
+                        out.extend(blocks.switch(BlockKind::Text, || json!({ "type": "text", "text": "" })));
+                        out.push(blocks.delta(json!({ "type": "text_delta", "text": text })));
+                    }
+                    ChatEvent::ReasoningDelta(thinking) => {
+                        out.extend(blocks.switch(BlockKind::Thinking, || json!({ "type": "thinking", "thinking": "" })));
+                        out.push(blocks.delta(json!({ "type": "thinking_delta", "thinking": thinking })));
+                    }
+                    ChatEvent::ToolCallDelta(delta) => {
+                        out.extend(blocks.switch(BlockKind::ToolUse(delta.index), || json!({
+                            "type": "tool_use",
+                            "id": delta.id.clone().unwrap_or_else(|| format!("toolu_{}", delta.index)),
+                            "name": delta.name.clone().unwrap_or_default(),
+                            "input": {},
+                        })));
+                        if !delta.arguments.is_empty() {
+                            out.push(blocks.delta(json!({ "type": "input_json_delta", "partial_json": delta.arguments })));
+                        }
+                    }
+                    ChatEvent::Done(_) => {
+                        out.extend(blocks.close());
+                        out.push(sse("message_delta", json!({
+                            "type": "message_delta",
+                            "delta": { "stop_reason": stop_reason(&collector), "stop_sequence": null },
+                            "usage": {
+                                "input_tokens": collector.metrics.prompt_eval_count,
+                                "output_tokens": collector.metrics.eval_count,
+                            },
+                        })));
+                        out.push(sse("message_stop", json!({ "type": "message_stop" })));
+                    }
+                    // the status code is sent already, the failure is reported in-band like
+                    // Anthropic does
+                    ChatEvent::Error(e) => {
+                        let e = ProxyError::from(e);
+                        e.log();
+                        yield Ok(sse("error", e.to_anthropic_json()));
+                        return;
+                    }
+                    ChatEvent::Usage(_) => {}
+                }
+                for event in out {
+                    yield Ok(event);
+                }
+            }
+            debug!("\n>>> messages(stream): {{{}}}", collector.content);
+        };
+
+        Ok(Sse::new(events).into_response())
+    }
+}
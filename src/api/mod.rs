@@ -0,0 +1,201 @@
+pub mod anthropic_api;
+pub mod ollama_api;
+pub mod openai_api;
+
+use crate::error::ProxyError;
+use crate::format_validation::validate_format;
+use crate::models::{self, Metrics, Model, ThinkingMode, ToolMode};
+use crate::providers::{
+    ChatCompletionRequest, ChatEvent, ChatEventStream, Provider, ProviderError, StopReason,
+    ToolCallAccumulator,
+};
+use crate::tool_emulation;
+use crate::AppState;
+use async_stream::stream;
+use axum::http::HeaderMap;
+use std::time::Instant;
+use tokio_stream::StreamExt;
+
+/// Follows a chat event stream and keeps what a final response needs: the whole answer, the stop
+/// reason and the metrics. Timings the upstream did not report are measured by the proxy, counting
+/// the time to first token as prompt evaluation and the rest as generation
+struct ChatCollector {
+    start: Instant,
+    first_token: Option<Instant>,
+    content: String,
+    thinking: String,
+    tool_calls: ToolCallAccumulator,
+    done_reason: Option<StopReason>,
+    metrics: Metrics,
+}
+
+impl ChatCollector {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            first_token: None,
+            content: String::new(),
+            thinking: String::new(),
+            tool_calls: ToolCallAccumulator::default(),
+            done_reason: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    fn push(&mut self, event: &ChatEvent) {
+        match event {
+            ChatEvent::ContentDelta(content) => {
+                self.first_token.get_or_insert_with(Instant::now);
+                self.content.push_str(content);
+            }
+            ChatEvent::ReasoningDelta(reasoning) => {
+                self.first_token.get_or_insert_with(Instant::now);
+                self.thinking.push_str(reasoning);
+            }
+            ChatEvent::ToolCallDelta(delta) => {
+                self.first_token.get_or_insert_with(Instant::now);
+                self.tool_calls.push(delta);
+            }
+            ChatEvent::Usage(metrics) => self.metrics = metrics.clone(),
+            ChatEvent::Done(reason) => {
+                self.done_reason = reason.clone();
+                self.measure();
+            }
+            ChatEvent::Error(_) => {}
+        }
+    }
+
+    fn measure(&mut self) {
+        let now = Instant::now();
+        let first_token = self.first_token.unwrap_or(now);
+        let metrics = &mut self.metrics;
+        if metrics.total_duration == 0 {
+            metrics.total_duration = (now - self.start).as_nanos() as u64;
+        }
+        if metrics.prompt_eval_duration == 0 {
+            metrics.prompt_eval_duration = (first_token - self.start).as_nanos() as u64;
+        }
+        if metrics.eval_duration == 0 {
+            metrics.eval_duration = (now - first_token).as_nanos() as u64;
+        }
+    }
+
+    fn done_reason(&self) -> Option<String> {
+        self.done_reason.as_ref().map(|r| r.as_str().to_string())
+    }
+
+    fn thinking(&self) -> Option<String> {
+        (!self.thinking.is_empty()).then(|| self.thinking.clone())
+    }
+
+    /// The assistant message collected so far
+    fn message(&self) -> models::Message {
+        models::Message {
+            thinking: self.thinking(),
+            tool_calls: (!self.tool_calls.is_empty()).then(|| self.tool_calls.tool_calls()),
+            ..models::Message::new("assistant", self.content.clone())
+        }
+    }
+}
+
+/// Runs a chat stream to its end and collects the response
+async fn collect_stream(
+    mut stream: ChatEventStream,
+    start: Instant,
+) -> Result<ChatCollector, ProviderError> {
+    let mut collector = ChatCollector::new(start);
+    while let Some(event) = stream.next().await {
+        if let ChatEvent::Error(e) = event {
+            return Err(e);
+        }
+        collector.push(&event);
+    }
+    Ok(collector)
+}
+
+/// Waits for the first event of a chat stream so that upstream failures (bad key, unreachable host)
+/// are reported with a proper status code instead of an already-started stream
+async fn peek_stream(mut stream: ChatEventStream) -> Result<ChatEventStream, ProviderError> {
+    match stream.next().await {
+        Some(ChatEvent::Error(e)) => Err(e),
+        first => Ok(Box::pin(futures::stream::iter(first).chain(stream))),
+    }
+}
+
+/// Applies a client's thinking mode to a chat stream: `field` keeps the reasoning events, `drop`
+/// removes them and `inline` turns them into content wrapped in `<think>...</think>`
+fn apply_thinking(stream: ChatEventStream, mode: ThinkingMode) -> ChatEventStream {
+    match mode {
+        ThinkingMode::Field => stream,
+        ThinkingMode::Drop => {
+            Box::pin(stream.filter(|event| !matches!(event, ChatEvent::ReasoningDelta(_))))
+        }
+        ThinkingMode::Inline => Box::pin(stream! {
+            let mut s = stream;
+            let mut thinking = false;
+            while let Some(event) = s.next().await {
+                match event {
+                    ChatEvent::ReasoningDelta(reasoning) if thinking => {
+                        yield ChatEvent::ContentDelta(reasoning);
+                    }
+                    ChatEvent::ReasoningDelta(reasoning) => {
+                        thinking = true;
+                        yield ChatEvent::ContentDelta(format!("<think>{}", reasoning));
+                    }
+                    ChatEvent::Usage(_) => yield event,
+                    event => {
+                        if thinking {
+                            thinking = false;
+                            yield ChatEvent::ContentDelta("</think>".to_string());
+                        }
+                        yield event;
+                    }
+                }
+            }
+        }),
+    }
+}
+
+/// Finds the provider serving a client-facing model name, and the model as the provider knows it
+async fn unmap_model(
+    model_name: String,
+    providers: &[Box<dyn Provider + Send + Sync>],
+) -> Result<(&(dyn Provider + Send + Sync), Model), ProxyError> {
+    for provider in providers {
+        let models = provider.get_models().await;
+        if let Some(model) = models.into_iter().find(|m| m.model == model_name) {
+            return Ok((provider.as_ref(), model));
+        }
+    }
+    Err(ProxyError::ModelNotFound(model_name))
+}
+
+/// Starts a chat with `target`, applying its per-model settings and the client's thinking mode,
+/// whatever API the request came in through
+async fn start_chat(
+    state: &AppState,
+    provider: &(dyn Provider + Send + Sync),
+    target: &Model,
+    mut request: ChatCompletionRequest,
+    headers: &HeaderMap,
+) -> Result<ChatEventStream, ProviderError> {
+    let emulate_tools =
+        target.config.tool_mode == Some(ToolMode::Emulated) && !request.tools.is_empty();
+    if emulate_tools {
+        tool_emulation::emulate_request(&mut request);
+    }
+    let stream = validate_format(
+        state.format_validation.as_ref(),
+        request.format.as_ref(),
+        || {
+            let stream = provider.chat(request.clone())?;
+            Ok(if emulate_tools {
+                tool_emulation::parse_stream(stream)
+            } else {
+                stream
+            })
+        },
+    )
+    .await?;
+    Ok(apply_thinking(stream, state.thinking_mode(headers)))
+}
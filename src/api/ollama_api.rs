@@ -0,0 +1,330 @@
+use super::{apply_thinking, collect_stream, peek_stream, start_chat, unmap_model, ChatCollector};
+use crate::error::ProxyError;
+use crate::format_validation::validate_format;
+use crate::models::{
+    self, ChatRequest, GenerateRequest, GenerateResponse, Model, ModelsResponse, ShowRequest,
+    ShowResponse, StreamChatChunk,
+};
+use crate::providers::{self, ChatCompletionRequest, ChatEvent, ProviderError};
+use crate::AppState;
+use async_stream::stream;
+use axum::{
+    extract::{rejection::JsonRejection, Json, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use futures::Stream;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+/// Serializes a stream as NDJSON. A provider failure mid-stream cannot change the status code any
+/// more, so it is reported in-band as a final `{"error": "..."}` line, like Ollama does
+fn ndjson_body<T>(
+    chunks: impl Stream<Item = Result<T, ProviderError>> + Send + 'static,
+) -> axum::body::Body
+where
+    T: Serialize + Send + 'static,
+{
+    let lines = stream! {
+        let mut chunks = Box::pin(chunks);
+        while let Some(item) = chunks.next().await {
+            let line = match item {
+                Ok(chunk) => serde_json::to_string(&chunk)
+                    .unwrap_or_else(|e| serde_json::json!({ "error": e.to_string() }).to_string()),
+                Err(e) => {
+                    let e = ProxyError::from(e);
+                    e.log();
+                    yield Ok::<_, std::convert::Infallible>(format!("{}\n", e.to_json()));
+                    return;
+                }
+            };
+            yield Ok(format!("{}\n", line));
+        }
+    };
+    axum::body::Body::from_stream(lines)
+}
+
+pub async fn handle_status(State(_state): State<Arc<AppState>>) -> impl IntoResponse {
+    "Ollama is running".to_string()
+}
+
+pub async fn handle_tags(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ModelsResponse>, ProxyError> {
+    // Collect all models from providers
+    let mut models: Vec<Model> = Vec::new();
+    for provider in &state.providers {
+        let mut provider_models = provider.get_models().await;
+        models.append(&mut provider_models);
+    }
+    debug!(
+        "models: {}",
+        models
+            .iter()
+            .map(|m| m.model.clone())
+            .collect::<Vec<String>>()
+            .join(",")
+    );
+    Ok(Json(ModelsResponse { models }))
+}
+
+/// What clients check before using a model: its details, context window and capabilities, as
+/// Ollama upstreams report them or as declared in the config
+pub async fn handle_show(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<ShowRequest>, JsonRejection>,
+) -> Result<Json<ShowResponse>, ProxyError> {
+    let Json(payload) = payload?;
+    let (provider, model) = unmap_model(payload.model, &state.providers).await?;
+    Ok(Json(provider.show(&model).await?))
+}
+
+pub async fn handle_generate(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Result<Json<GenerateRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, ProxyError> {
+    let Json(payload) = payload?;
+    let start = Instant::now();
+    let (provider, model) = unmap_model(payload.model.clone(), &state.providers).await?;
+    let model = model.name;
+
+    let history = match &payload.context {
+        Some(context) => state.contexts.get(context),
+        None => Vec::new(),
+    };
+    let stream = validate_format(
+        state.format_validation.as_ref(),
+        payload.format.as_ref(),
+        || provider.generate(&model, &payload, &history),
+    )
+    .await?;
+    let stream = apply_thinking(stream, state.thinking_mode(&headers));
+
+    // raw prompts and fill-in-the-middle are one-shot, like in Ollama they get no context
+    let mut conversation = if payload.raw || payload.suffix.is_some() {
+        None
+    } else {
+        Some(providers::generate_conversation(&payload, &history))
+    };
+
+    let stream_mode = payload.stream.unwrap_or(true);
+    if !stream_mode {
+        // Collect all chunks from the stream and concatenate content
+        let collected = collect_stream(stream, start).await?;
+
+        let context = conversation.map(|mut messages| {
+            messages.push(models::Message::new("assistant", collected.content.clone()));
+            state.contexts.insert(messages)
+        });
+        let resp = GenerateResponse {
+            model,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            done: true,
+            done_reason: collected.done_reason(),
+            thinking: collected.thinking(),
+            context,
+            metrics: Some(collected.metrics),
+            response: collected.content,
+        };
+
+        debug!(
+            "\n<<< generate: {{{}}} \n>>> response: {{{}}}",
+            payload.prompt, resp.response
+        );
+        Ok(Json(resp).into_response())
+
+    // stream mode
+    } else {
+        let prompt_for_log = payload.prompt;
+        let stream = peek_stream(stream).await?;
+        let state = state.clone();
+        let generate_stream = stream! {
+            let mut collector = ChatCollector::new(start);
+            let mut s = stream;
+            while let Some(event) = s.next().await {
+                collector.push(&event);
+                match event {
+                    ChatEvent::ContentDelta(content) => yield Ok(GenerateResponse {
+                        model: model.clone(),
+                        created_at: chrono::Utc::now().to_rfc3339(),
+                        response: content,
+                        thinking: None,
+                        done: false,
+                        done_reason: None,
+                        context: None,
+                        metrics: None,
+                    }),
+                    ChatEvent::ReasoningDelta(reasoning) => yield Ok(GenerateResponse {
+                        model: model.clone(),
+                        created_at: chrono::Utc::now().to_rfc3339(),
+                        response: String::new(),
+                        thinking: Some(reasoning),
+                        done: false,
+                        done_reason: None,
+                        context: None,
+                        metrics: None,
+                    }),
+                    ChatEvent::Done(_) => {
+                        let context = conversation.take().map(|mut messages| {
+                            messages.push(models::Message::new("assistant", collector.content.clone()));
+                            state.contexts.insert(messages)
+                        });
+                        yield Ok(GenerateResponse {
+                            model: model.clone(),
+                            created_at: chrono::Utc::now().to_rfc3339(),
+                            response: String::new(),
+                            thinking: None,
+                            done: true,
+                            done_reason: collector.done_reason(),
+                            context,
+                            metrics: Some(collector.metrics.clone()),
+                        });
+                    }
+                    ChatEvent::Error(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+            debug!("\n<<< generate(stream): {{{}}} \n>>> response: {{{}}}", prompt_for_log, collector.content);
+        };
+
+        Ok((
+            [(
+                axum::http::header::CONTENT_TYPE,
+                "application/x-ndjson".to_string(),
+            )],
+            ndjson_body(generate_stream),
+        )
+            .into_response())
+    }
+}
+
+pub async fn handle_chat(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Result<Json<ChatRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, ProxyError> {
+    let Json(payload) = payload?;
+    // Use streaming method for both streaming and non-streaming requests
+    let start = Instant::now();
+    let (provider, target) = unmap_model(payload.model, &state.providers).await?;
+    let model = target.name.clone();
+
+    // Log chat similar to generate: last user message and response
+    let last_user_message = payload
+        .messages
+        .iter()
+        .rfind(|m| m.role == "user")
+        .map(|m| m.content.clone())
+        .unwrap_or_default();
+
+    let request = ChatCompletionRequest {
+        model: model.clone(),
+        messages: payload.messages,
+        tools: payload.tools,
+        format: payload.format,
+        think: payload.think,
+        options: payload.options,
+    };
+    let stream = start_chat(&state, provider, &target, request, &headers).await?;
+
+    let stream_mode = payload.stream.unwrap_or(true);
+    if !stream_mode {
+        // Non-streaming: collect all chunks from a stream and concatenate content
+        let collected = collect_stream(stream, start).await?;
+
+        let resp = models::ChatResponse {
+            model,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            done: true,
+            done_reason: collected.done_reason(),
+            message: collected.message(),
+            metrics: collected.metrics,
+        };
+
+        debug!(
+            "\n<<< chat: {{{}}} \n>>> response {{{}}}",
+            last_user_message, resp.message.content
+        );
+
+        Ok(Json(resp).into_response())
+
+    // stream mode
+    } else {
+        // Streaming mode with logging similar to generate
+        let stream = peek_stream(stream).await?;
+        let chat_stream = stream! {
+            let mut collector = ChatCollector::new(start);
+            let mut s = stream;
+            while let Some(event) = s.next().await {
+                collector.push(&event);
+                match event {
+                    ChatEvent::ContentDelta(content) => yield Ok(StreamChatChunk {
+                        model: model.clone(),
+                        created_at: chrono::Utc::now().to_rfc3339(),
+                        message: models::Message::new("assistant", content),
+                        done: false,
+                        done_reason: None,
+                        metrics: None,
+                    }),
+                    ChatEvent::ReasoningDelta(reasoning) => yield Ok(StreamChatChunk {
+                        model: model.clone(),
+                        created_at: chrono::Utc::now().to_rfc3339(),
+                        message: models::Message {
+                            thinking: Some(reasoning),
+                            ..models::Message::new("assistant", String::new())
+                        },
+                        done: false,
+                        done_reason: None,
+                        metrics: None,
+                    }),
+                    ChatEvent::Done(_) => {
+                        // like Ollama, the complete tool calls come in their own chunk
+                        if !collector.tool_calls.is_empty() {
+                            yield Ok(StreamChatChunk {
+                                model: model.clone(),
+                                created_at: chrono::Utc::now().to_rfc3339(),
+                                message: models::Message {
+                                    tool_calls: Some(collector.tool_calls.tool_calls()),
+                                    ..models::Message::new("assistant", String::new())
+                                },
+                                done: false,
+                                done_reason: None,
+                                metrics: None,
+                            });
+                        }
+                        yield Ok(StreamChatChunk {
+                            model: model.clone(),
+                            created_at: chrono::Utc::now().to_rfc3339(),
+                            message: models::Message::new("assistant", String::new()),
+                            done: true,
+                            done_reason: collector.done_reason(),
+                            metrics: Some(collector.metrics.clone()),
+                        });
+                    }
+                    ChatEvent::Error(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+            debug!("\n<<< chat(stream): {{{}}} \n>>> response {{{}}}", last_user_message, collector.content);
+        };
+
+        Ok((
+            [(
+                axum::http::header::CONTENT_TYPE,
+                "application/x-ndjson".to_string(),
+            )],
+            ndjson_body(chat_stream),
+        )
+            .into_response())
+    }
+}
@@ -0,0 +1,440 @@
+use super::{collect_stream, peek_stream, start_chat, unmap_model, ChatCollector};
+use crate::error::ProxyError;
+use crate::models::{Message, Metrics, ModelOptions, Think, ToolCall, ToolCallFunction};
+use crate::providers::{ChatCompletionRequest, ChatEvent, ProviderError};
+use crate::AppState;
+use async_stream::stream;
+use axum::{
+    extract::{rejection::JsonRejection, Json, State},
+    http::HeaderMap,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+/// A `ProxyError` answered in OpenAI's error shape
+pub struct OpenaiError(ProxyError);
+
+impl From<ProxyError> for OpenaiError {
+    fn from(e: ProxyError) -> Self {
+        OpenaiError(e)
+    }
+}
+
+impl From<ProviderError> for OpenaiError {
+    fn from(e: ProviderError) -> Self {
+        OpenaiError(ProxyError::from(e))
+    }
+}
+
+impl From<JsonRejection> for OpenaiError {
+    fn from(e: JsonRejection) -> Self {
+        OpenaiError(ProxyError::from(e))
+    }
+}
+
+impl IntoResponse for OpenaiError {
+    fn into_response(self) -> Response {
+        self.0.log();
+        (self.0.status_code(), Json(self.0.to_openai_json())).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiChatRequest {
+    model: String,
+    messages: Vec<OpenaiMessage>,
+    #[serde(default)]
+    tools: Vec<Value>,
+    #[serde(default)]
+    stream: bool,
+    stream_options: Option<StreamOptions>,
+    // max_tokens is the legacy name of max_completion_tokens
+    max_tokens: Option<i64>,
+    max_completion_tokens: Option<i64>,
+    stop: Option<Stop>,
+    seed: Option<i64>,
+    top_p: Option<f64>,
+    temperature: Option<f64>,
+    presence_penalty: Option<f64>,
+    frequency_penalty: Option<f64>,
+    response_format: Option<Value>,
+    reasoning_effort: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct OpenaiMessage {
+    role: String,
+    content: Option<Content>,
+    #[serde(default)]
+    tool_calls: Vec<OpenaiToolCall>,
+    // on `tool` messages: the call this answers
+    tool_call_id: Option<String>,
+}
+
+// plain text, or content parts when the message carries images
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ImageUrl {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct OpenaiToolCall {
+    id: Option<String>,
+    function: OpenaiFunction,
+}
+
+#[derive(Deserialize)]
+struct OpenaiFunction {
+    name: String,
+    // a JSON-encoded string, some clients send the object itself
+    #[serde(default)]
+    arguments: Value,
+}
+
+impl OpenaiChatRequest {
+    /// The request in the proxy's internal protocol, addressed to `model` as the upstream knows it
+    fn into_chat_request(self, model: String) -> ChatCompletionRequest {
+        let format = match &self.response_format {
+            Some(format) => match format["type"].as_str() {
+                Some("json_object") => Some(json!("json")),
+                Some("json_schema") => Some(format["json_schema"]["schema"].clone()),
+                _ => None,
+            },
+            None => None,
+        };
+        let stop = self.stop.map(|stop| match stop {
+            Stop::One(stop) => vec![stop],
+            Stop::Many(stops) => stops,
+        });
+        let options = ModelOptions {
+            num_predict: self.max_completion_tokens.or(self.max_tokens),
+            stop,
+            seed: self.seed,
+            top_p: self.top_p,
+            temperature: self.temperature,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            ..Default::default()
+        };
+        ChatCompletionRequest {
+            model,
+            messages: to_messages(self.messages),
+            tools: self.tools,
+            format,
+            think: self.reasoning_effort.map(Think::Effort),
+            options: (options != ModelOptions::default()).then_some(options),
+        }
+    }
+}
+
+/// Translates OpenAI messages into Ollama-style ones. Tool results get the name of the function
+/// they answer, which Ollama upstreams need
+fn to_messages(messages: Vec<OpenaiMessage>) -> Vec<Message> {
+    let mut names: HashMap<String, String> = HashMap::new();
+    messages
+        .into_iter()
+        .map(|m| {
+            let mut content = Vec::new();
+            let mut images = Vec::new();
+            match m.content {
+                Some(Content::Text(text)) => content.push(text),
+                Some(Content::Parts(parts)) => {
+                    for part in parts {
+                        match part {
+                            ContentPart::Text { text } => content.push(text),
+                            // Ollama images are bare base64, data URIs are unwrapped
+                            ContentPart::ImageUrl { image_url } => {
+                                images.push(match image_url.url.split_once(";base64,") {
+                                    Some((_, data)) => data.to_string(),
+                                    None => image_url.url,
+                                })
+                            }
+                            ContentPart::Other => {}
+                        }
+                    }
+                }
+                None => {}
+            }
+
+            let tool_calls: Vec<ToolCall> = m
+                .tool_calls
+                .into_iter()
+                .map(|call| {
+                    if let Some(id) = &call.id {
+                        names.insert(id.clone(), call.function.name.clone());
+                    }
+                    let arguments = match call.function.arguments {
+                        Value::String(arguments) => {
+                            serde_json::from_str(&arguments).unwrap_or(Value::String(arguments))
+                        }
+                        arguments => arguments,
+                    };
+                    ToolCall {
+                        id: call.id,
+                        function: ToolCallFunction {
+                            index: None,
+                            name: call.function.name,
+                            arguments,
+                        },
+                    }
+                })
+                .collect();
+
+            let role = match m.role.as_str() {
+                // the newer name of the system role
+                "developer" => "system",
+                role => role,
+            };
+            Message {
+                images: (!images.is_empty()).then_some(images),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                tool_name: m
+                    .tool_call_id
+                    .as_ref()
+                    .and_then(|id| names.get(id).cloned()),
+                tool_call_id: m.tool_call_id,
+                ..Message::new(role, content.join("\n"))
+            }
+        })
+        .collect()
+}
+
+/// Tool calls in OpenAI's shape: with ids and JSON-encoded arguments
+fn to_openai_tool_calls(tool_calls: &[ToolCall]) -> Vec<Value> {
+    tool_calls
+        .iter()
+        .enumerate()
+        .map(|(i, call)| {
+            let arguments = match &call.function.arguments {
+                Value::String(arguments) => arguments.clone(),
+                arguments => arguments.to_string(),
+            };
+            json!({
+                "id": call.id.clone().unwrap_or_else(|| format!("call_{}", i)),
+                "type": "function",
+                "function": { "name": call.function.name, "arguments": arguments },
+            })
+        })
+        .collect()
+}
+
+/// The `finish_reason` of a collected answer. Ollama upstreams say `stop` after tool calls, OpenAI
+/// clients look for `tool_calls`
+fn finish_reason(collected: &ChatCollector) -> String {
+    match collected.done_reason() {
+        Some(reason) if reason != "stop" => reason,
+        _ if !collected.tool_calls.is_empty() => "tool_calls".to_string(),
+        _ => "stop".to_string(),
+    }
+}
+
+fn usage(metrics: &Metrics) -> Value {
+    json!({
+        "prompt_tokens": metrics.prompt_eval_count,
+        "completion_tokens": metrics.eval_count,
+        "total_tokens": metrics.prompt_eval_count + metrics.eval_count,
+    })
+}
+
+/// Builds the `chat.completion.chunk` events of one streamed response
+struct ChunkEncoder {
+    id: String,
+    created: i64,
+    model: String,
+}
+
+impl ChunkEncoder {
+    fn event(&self, choices: Value, usage: Option<Value>) -> Event {
+        let mut chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        });
+        if let Some(usage) = usage {
+            chunk["usage"] = usage;
+        }
+        Event::default().data(chunk.to_string())
+    }
+
+    fn delta(&self, delta: Value, finish_reason: Option<String>) -> Event {
+        self.event(
+            json!([{ "index": 0, "delta": delta, "finish_reason": finish_reason }]),
+            None,
+        )
+    }
+}
+
+pub async fn handle_models(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let mut data = Vec::new();
+    for provider in &state.providers {
+        for model in provider.get_models().await {
+            data.push(json!({
+                "id": model.model,
+                "object": "model",
+                "created": 0,
+                "owned_by": "ollama-proxy",
+            }));
+        }
+    }
+    Json(json!({ "object": "list", "data": data }))
+}
+
+pub async fn handle_chat_completions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Result<Json<OpenaiChatRequest>, JsonRejection>,
+) -> Result<Response, OpenaiError> {
+    let Json(payload) = payload?;
+    let start = Instant::now();
+    let (provider, target) = unmap_model(payload.model.clone(), &state.providers).await?;
+
+    // like OpenAI, answer with the model name the client asked for
+    let model = payload.model.clone();
+    let stream_mode = payload.stream;
+    let include_usage = payload
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    let request = payload.into_chat_request(target.name.clone());
+    let stream = start_chat(&state, provider, &target, request, &headers).await?;
+
+    let created = chrono::Utc::now().timestamp();
+    let id = format!(
+        "chatcmpl-{:x}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+
+    if !stream_mode {
+        let collected = collect_stream(stream, start).await?;
+
+        let mut message = json!({ "role": "assistant", "content": collected.content });
+        if let Some(thinking) = collected.thinking() {
+            message["reasoning_content"] = json!(thinking);
+        }
+        if !collected.tool_calls.is_empty() {
+            message["tool_calls"] = json!(to_openai_tool_calls(&collected.tool_calls.tool_calls()));
+            if collected.content.is_empty() {
+                message["content"] = Value::Null;
+            }
+        }
+        let resp = json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": message,
+                "finish_reason": finish_reason(&collected),
+            }],
+            "usage": usage(&collected.metrics),
+        });
+
+        debug!("\n>>> chat completion: {{{}}}", collected.content);
+        Ok(Json(resp).into_response())
+
+    // stream mode
+    } else {
+        let stream = peek_stream(stream).await?;
+        let encoder = ChunkEncoder { id, created, model };
+        let events = stream! {
+            let mut collector = ChatCollector::new(start);
+            // tool calls that got their id already
+            let mut started_calls = HashSet::new();
+            let mut s = stream;
+            yield Ok::<_, std::convert::Infallible>(
+                encoder.delta(json!({ "role": "assistant", "content": "" }), None),
+            );
+            while let Some(event) = s.next().await {
+                collector.push(&event);
+                match event {
+                    ChatEvent::ContentDelta(content) => {
+                        yield Ok(encoder.delta(json!({ "content": content }), None));
+                    }
+                    ChatEvent::ReasoningDelta(reasoning) => {
+                        yield Ok(encoder.delta(json!({ "reasoning_content": reasoning }), None));
+                    }
+                    ChatEvent::ToolCallDelta(delta) => {
+                        let mut call = json!({
+                            "index": delta.index,
+                            "function": { "arguments": delta.arguments },
+                        });
+                        if started_calls.insert(delta.index) {
+                            call["id"] = json!(delta.id.unwrap_or_else(|| format!("call_{}", delta.index)));
+                            call["type"] = json!("function");
+                        }
+                        if let Some(name) = delta.name {
+                            call["function"]["name"] = json!(name);
+                        }
+                        yield Ok(encoder.delta(json!({ "tool_calls": [call] }), None));
+                    }
+                    ChatEvent::Done(_) => {
+                        let finish_reason = finish_reason(&collector);
+                        yield Ok(encoder.delta(json!({}), Some(finish_reason)));
+                        if include_usage {
+                            yield Ok(encoder.event(json!([]), Some(usage(&collector.metrics))));
+                        }
+                        yield Ok(Event::default().data("[DONE]"));
+                    }
+                    // the status code is sent already, the failure is reported in-band as an
+                    // `error` event, which the openai SDKs raise, and the stream is ended as usual
+                    ChatEvent::Error(e) => {
+                        let e = ProxyError::from(e);
+                        e.log();
+                        yield Ok(Event::default().event("error").data(e.to_openai_json().to_string()));
+                        yield Ok(Event::default().data("[DONE]"));
+                        return;
+                    }
+                    ChatEvent::Usage(_) => {}
+                }
+            }
+            debug!("\n>>> chat completion(stream): {{{}}}", collector.content);
+        };
+
+        Ok(Sse::new(events).into_response())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::models::{ContextConfig, Message};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Server-side memory of `/api/generate` conversations.
+///
+/// Ollama hands out the tokenized conversation as `context`; the proxy cannot tokenize for its
+/// upstreams, so it keeps the turns itself and hands out an opaque handle in that array instead.
+/// Handles are 64-bit ids drawn from a per-process random key, so they can be neither guessed nor
+/// resolved by another run of the proxy.
+pub struct ContextStore {
+    ttl: Duration,
+    max_entries: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    // hashing a counter with this randomly seeded key gives unpredictable ids
+    id_key: RandomState,
+    counter: u64,
+    entries: HashMap<u64, Entry>,
+}
+
+struct Entry {
+    messages: Vec<Message>,
+    last_used: Instant,
+}
+
+impl ContextStore {
+    pub fn new(config: &ContextConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_entries: config.max_entries,
+            inner: Mutex::new(Inner {
+                id_key: RandomState::new(),
+                counter: 0,
+                entries: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Returns the conversation a `context` handle refers to, empty when it is unknown or expired
+    pub fn get(&self, context: &[i32]) -> Vec<Message> {
+        let &[high, low] = context else {
+            warn!("ignoring context that was not issued by this proxy");
+            return Vec::new();
+        };
+        let id = ((high as u32 as u64) << 32) | low as u32 as u64;
+        let mut inner = self.inner.lock().unwrap();
+        match inner.entries.get_mut(&id) {
+            Some(entry) if entry.last_used.elapsed() < self.ttl => {
+                entry.last_used = Instant::now();
+                entry.messages.clone()
+            }
+            _ => {
+                warn!("context {} is unknown or expired, starting over", id);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Stores a conversation and returns the handle to give back as `context`
+    pub fn insert(&self, messages: Vec<Message>) -> Vec<i32> {
+        let mut inner = self.inner.lock().unwrap();
+        let ttl = self.ttl;
+        inner
+            .entries
+            .retain(|_, entry| entry.last_used.elapsed() < ttl);
+        while inner.entries.len() >= self.max_entries.max(1) {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| *id);
+            match oldest {
+                Some(id) => inner.entries.remove(&id),
+                None => break,
+            };
+        }
+
+        let id = loop {
+            inner.counter += 1;
+            let id = inner.id_key.hash_one(inner.counter);
+            if !inner.entries.contains_key(&id) {
+                break id;
+            }
+        };
+        inner.entries.insert(
+            id,
+            Entry {
+                messages,
+                last_used: Instant::now(),
+            },
+        );
+        vec![(id >> 32) as i32, id as i32]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(ttl_secs: u64, max_entries: usize) -> ContextStore {
+        ContextStore::new(&ContextConfig {
+            ttl_secs,
+            max_entries,
+        })
+    }
+
+    fn turn(content: &str) -> Vec<Message> {
+        vec![Message {
+            role: "user".to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }]
+    }
+
+    fn content(messages: Vec<Message>) -> Vec<String> {
+        messages.into_iter().map(|m| m.content).collect()
+    }
+
+    #[test]
+    fn handles_give_back_their_conversation() {
+        let store = store(60, 10);
+        let first = store.insert(turn("first"));
+        let second = store.insert(turn("second"));
+        assert_ne!(first, second);
+        assert_eq!(content(store.get(&first)), ["first"]);
+        assert_eq!(content(store.get(&second)), ["second"]);
+    }
+
+    #[test]
+    fn unknown_handles_start_over() {
+        let store = store(60, 10);
+        let handle = store.insert(turn("first"));
+        assert!(store
+            .get(&[handle[0], handle[1].wrapping_add(1)])
+            .is_empty());
+        assert!(store.get(&[1, 2, 3]).is_empty());
+        assert!(store.get(&[]).is_empty());
+    }
+
+    #[test]
+    fn expired_conversations_are_forgotten() {
+        let store = store(0, 10);
+        let handle = store.insert(turn("first"));
+        assert!(store.get(&handle).is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted_at_max_entries() {
+        let store = store(60, 2);
+        let first = store.insert(turn("first"));
+        std::thread::sleep(Duration::from_millis(2));
+        let second = store.insert(turn("second"));
+        std::thread::sleep(Duration::from_millis(2));
+        // using `first` again makes `second` the one to go
+        store.get(&first);
+        std::thread::sleep(Duration::from_millis(2));
+        let third = store.insert(turn("third"));
+        assert_eq!(content(store.get(&first)), ["first"]);
+        assert!(store.get(&second).is_empty());
+        assert_eq!(content(store.get(&third)), ["third"]);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::providers::{ProviderError, ProviderErrorKind};
+use axum::extract::rejection::JsonRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+use tracing::error;
+
+/// Errors surfaced to clients by the request handlers.
+///
+/// Every variant is rendered the way Ollama reports failures: a status code and a
+/// `{"error": "..."}` body, so clients can show a readable message.
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The requested model is not served by any configured provider
+    ModelNotFound(String),
+    /// The provider failed while handling the request
+    Provider(ProviderError),
+    /// The request body is not the JSON the endpoint takes
+    InvalidBody(JsonRejection),
+    /// No endpoint answers this path
+    RouteNotFound(String),
+    /// The endpoint answers other methods only
+    MethodNotAllowed(String),
+}
+
+impl ProxyError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ProxyError::ModelNotFound(_) | ProxyError::RouteNotFound(_) => StatusCode::NOT_FOUND,
+            ProxyError::InvalidBody(rejection) => rejection.status(),
+            ProxyError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            ProxyError::Provider(e) => match e.kind {
+                ProviderErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+                ProviderErrorKind::InvalidRequest => StatusCode::BAD_REQUEST,
+                ProviderErrorKind::Connect | ProviderErrorKind::Decode => StatusCode::BAD_GATEWAY,
+                ProviderErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                // client-side upstream failures (bad key, rate limit, ...) keep their status,
+                // upstream server failures become a bad gateway
+                ProviderErrorKind::Status(code) => match StatusCode::from_u16(code) {
+                    Ok(status) if status.is_client_error() => status,
+                    _ => StatusCode::BAD_GATEWAY,
+                },
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::ModelNotFound(model) => write!(f, "model '{}' not found", model),
+            ProxyError::InvalidBody(rejection) => write!(f, "{}", rejection.body_text()),
+            ProxyError::RouteNotFound(route) => write!(f, "no endpoint for {}", route),
+            ProxyError::MethodNotAllowed(route) => write!(f, "{} is not allowed", route),
+            ProxyError::Provider(e) if e.kind == ProviderErrorKind::InvalidRequest => {
+                write!(f, "{}", e.message)
+            }
+            ProxyError::Provider(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl From<ProviderError> for ProxyError {
+    fn from(e: ProviderError) -> Self {
+        ProxyError::Provider(e)
+    }
+}
+
+impl From<JsonRejection> for ProxyError {
+    fn from(e: JsonRejection) -> Self {
+        ProxyError::InvalidBody(e)
+    }
+}
+
+impl ProxyError {
+    /// Logs the error; the upstream url stays in the log only, it may carry credentials or
+    /// internal hosts
+    pub fn log(&self) {
+        match self {
+            ProxyError::Provider(ProviderError {
+                request_url: Some(url),
+                ..
+            }) => error!("{} (request url: {})", self, url),
+            _ => error!("{}", self),
+        }
+    }
+
+    /// The Ollama error body, `{"error": "..."}`
+    pub fn to_json(&self) -> Value {
+        json!({ "error": self.to_string() })
+    }
+
+    /// The OpenAI error body, `{"error": {"message": "...", "type": "...", "code": ...}}`
+    pub fn to_openai_json(&self) -> Value {
+        let (kind, code) = match self {
+            ProxyError::ModelNotFound(_) => ("invalid_request_error", Some("model_not_found")),
+            _ if self.status_code().is_client_error() => ("invalid_request_error", None),
+            _ => ("api_error", None),
+        };
+        json!({ "error": { "message": self.to_string(), "type": kind, "code": code } })
+    }
+
+    /// The Anthropic error body, `{"type": "error", "error": {"type": "...", "message": "..."}}`
+    pub fn to_anthropic_json(&self) -> Value {
+        let kind = match self.status_code().as_u16() {
+            400 | 415 | 422 => "invalid_request_error",
+            401 => "authentication_error",
+            403 => "permission_error",
+            404 => "not_found_error",
+            429 => "rate_limit_error",
+            _ => "api_error",
+        };
+        json!({ "type": "error", "error": { "type": kind, "message": self.to_string() } })
+    }
+}
+
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        self.log();
+        (self.status_code(), Json(self.to_json())).into_response()
+    }
+}
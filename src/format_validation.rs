@@ -0,0 +1,84 @@
+use crate::models::FormatValidationConfig;
+use crate::providers::{ChatEvent, ChatEventStream, ProviderError, ProviderErrorKind};
+use jsonschema::Validator;
+use serde_json::Value;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+/// Runs `chat` and checks its answer against the requested `format`, asking again up to
+/// `config.retries` times when the model produced invalid JSON. The checked answer is replayed as
+/// a stream, so streaming clients only get it once it is complete. Without a config or a format,
+/// `chat` runs unchecked
+pub async fn validate_format(
+    config: Option<&FormatValidationConfig>,
+    format: Option<&Value>,
+    chat: impl Fn() -> Result<ChatEventStream, ProviderError>,
+) -> Result<ChatEventStream, ProviderError> {
+    let (Some(config), Some(format)) = (config, format) else {
+        return chat();
+    };
+    let validator = match format {
+        Value::Object(_) => Some(jsonschema::validator_for(format).map_err(|e| {
+            ProviderError::invalid_request(format!("'format' is not a valid JSON schema: {}", e))
+        })?),
+        _ => None,
+    };
+
+    let mut attempt = 0;
+    loop {
+        let mut stream = chat()?;
+        let mut events = Vec::new();
+        let mut content = String::new();
+        let mut tool_calls = false;
+        while let Some(event) = stream.next().await {
+            match &event {
+                ChatEvent::ContentDelta(delta) => content.push_str(delta),
+                ChatEvent::ToolCallDelta(_) => tool_calls = true,
+                ChatEvent::Error(_) => {
+                    // the failure is the client's answer, replay what came before it as well
+                    events.push(event);
+                    return Ok(Box::pin(futures::stream::iter(events)));
+                }
+                _ => {}
+            }
+            events.push(event);
+        }
+
+        // an answer made of tool calls has no content to check
+        let checked = if tool_calls {
+            Ok(())
+        } else {
+            check(validator.as_ref(), &content)
+        };
+        match checked {
+            Ok(()) => return Ok(Box::pin(futures::stream::iter(events))),
+            Err(e) if attempt < config.retries => {
+                attempt += 1;
+                warn!(
+                    "answer does not match the requested format ({}), retrying ({}/{})",
+                    e, attempt, config.retries
+                );
+            }
+            Err(e) => {
+                return Err(ProviderError {
+                    kind: ProviderErrorKind::Decode,
+                    message: format!(
+                        "the model's answer does not match the requested format: {}",
+                        e
+                    ),
+                    request_url: None,
+                });
+            }
+        }
+    }
+}
+
+/// Checks that `content` is JSON and, when a schema was requested, that it follows the schema
+fn check(validator: Option<&Validator>, content: &str) -> Result<(), String> {
+    let instance: Value =
+        serde_json::from_str(content.trim()).map_err(|e| format!("not valid JSON: {}", e))?;
+    match validator {
+        Some(validator) => validator.validate(&instance).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
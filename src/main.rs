@@ -1,236 +1,68 @@
-use async_stream::stream;
 use axum::routing::{get, post};
-use futures_util::TryStreamExt;
 // Make sure this is in scope
 use std::path::Path;
 use std::{env, fs};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing::{debug, error, info};
+use tracing::{info, warn};
+mod api;
+mod context_store;
+mod error;
+mod format_validation;
+mod model_discovery;
 mod models;
 mod providers;
+mod tool_emulation;
 
 use providers::Provider;
 struct AppState {
     providers: Vec<Box<dyn Provider + Send + Sync>>,
+    contexts: ContextStore,
+    thinking: ThinkingConfig,
+    format_validation: Option<FormatValidationConfig>,
 }
 
+impl AppState {
+    /// How the client that sent `headers` wants reasoning returned
+    fn thinking_mode(&self, headers: &HeaderMap) -> ThinkingMode {
+        let user_agent = headers
+            .get(axum::http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        self.thinking.mode_for(user_agent)
+    }
+}
+
+use crate::api::{anthropic_api, ollama_api, openai_api};
+use crate::context_store::ContextStore;
+use crate::error::ProxyError;
+use crate::model_discovery::DiscoveringProvider;
 use crate::models::{
-    ApiType, ChatRequest, Config, GenerateRequest, GenerateResponse, Model, ModelsResponse,
+    ApiType, Config, DiscoveryConfig, FormatValidationConfig, Model, ThinkingConfig, ThinkingMode,
 };
 
+use crate::providers::anthropic_provider::AnthropicProvider;
+use crate::providers::bedrock_provider::BedrockProvider;
+use crate::providers::gemini_provider::GeminiProvider;
+use crate::providers::llamacpp_provider::LlamaCppProvider;
 use crate::providers::ollama_provider::OllamaProvider;
 use crate::providers::openai_provider::OpenAIProvider;
+use crate::providers::openai_responses_provider::OpenAIResponsesProvider;
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{HeaderMap, Method, Uri},
     Router,
 };
 use std::sync::Arc;
-use tokio_stream::StreamExt;
 
-/// Collects all content from a chat stream and concatenates it into a single string
-async fn collect_content_from_stream(mut stream: providers::ChatChunkStream) -> Result<String, ()> {
-    let mut content = String::new();
-
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(chunk) => {
-                if !chunk.done {
-                    content.push_str(&chunk.message.content);
-                }
-            }
-            Err(_) => return Err(()),
-        }
-    }
-
-    Ok(content)
-}
 pub fn map_model_name(provider_name: &String, model_name: &String) -> String {
     format!("[{}]-{}", provider_name, model_name)
 }
-async fn unmap_model(
-    model_name: String,
-    providers: &Vec<Box<dyn Provider + Send + Sync>>,
-) -> (&Box<dyn Provider + Send + Sync>, String) {
-    for provider in providers {
-        let models = provider.get_models().await;
-        if let Some(model) = models.iter().find(|m| m.model == model_name) {
-            return (provider, model.name.clone());
-        }
-    }
-    panic!(
-        "Model '{}' not found in any provider, but that is impossible",
-        model_name
-    )
-}
-async fn handle_status(State(_state): State<Arc<AppState>>) -> impl IntoResponse {
-    "Ollama is running".to_string()
-}
-
-async fn handle_tags(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<ModelsResponse>, (StatusCode, String)> {
-    // Collect all models from providers
-    let mut models: Vec<Model> = Vec::new();
-    for provider in &state.providers {
-        let mut provider_models = provider.get_models().await;
-        models.append(&mut provider_models);
-    }
-    debug!(
-        "models: {}",
-        models
-            .iter()
-            .map(|m| m.model.clone())
-            .collect::<Vec<String>>()
-            .join(",")
-    );
-    Ok(Json(ModelsResponse { models }))
-}
-
-async fn handle_generate(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<GenerateRequest>,
-) -> Result<Json<GenerateResponse>, (StatusCode, String)> {
-    // Create a simple message for chat
-    let messages = vec![models::Message {
-        role: "user".to_string(),
-        content: payload.prompt.clone(),
-    }];
-
-    // Use the provider's chat_stream method to generate a response
-    let (provider, model) = unmap_model(payload.model, &state.providers).await;
-
-    let stream = match provider.chat(&model, &messages, payload.options.clone()) {
-        Ok(stream) => stream,
-        Err(_) => {
-            panic!("provider error during generate: chat initialization failed");
-        }
-    };
-
-    // Collect all chunks from the stream and concatenate content
-    let content = match collect_content_from_stream(stream).await {
-        Ok(content) => content,
-        Err(_) => {
-            panic!("provider error during generate: stream failed");
-        }
-    };
-
-    let resp = GenerateResponse {
-        model,
-        created_at: chrono::Utc::now().to_rfc3339(),
-        response: content,
-        done: true,
-        context: None,
-        total_duration: 0,
-        load_duration: 0,
-        prompt_eval_count: 0,
-        eval_count: 0,
-        eval_duration: 0,
-    };
-
-    debug!(
-        "\n<<< generate: {{{}}} \n>>> response: {{{}}}",
-        payload.prompt, resp.response
-    );
-    Ok(Json(resp))
+// 处理未匹配路由的函数
+async fn not_found(method: Method, uri: Uri) -> ProxyError {
+    ProxyError::RouteNotFound(format!("{} {}", method, uri.path()))
 }
 
-async fn handle_chat(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<ChatRequest>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // Use streaming method for both streaming and non-streaming requests
-    let (provider, model) = unmap_model(payload.model, &state.providers).await;
-
-    let stream = match provider.chat(&model, &payload.messages, payload.options.clone()) {
-        Ok(stream) => stream,
-        Err(_) => {
-            panic!("provider error during chat: chat initialization failed");
-        }
-    };
-
-    let stream_mode = payload.stream.unwrap_or(true);
-    if !stream_mode {
-        // Non-streaming: collect all chunks from a stream and concatenate content
-        let content = match collect_content_from_stream(stream).await {
-            Ok(content) => content,
-            Err(_) => {
-                panic!("provider error during chat: stream failed");
-            }
-        };
-
-        let resp = models::ChatResponse {
-            model,
-            created_at: chrono::Utc::now().to_rfc3339(),
-            message: models::Message {
-                role: "assistant".to_string(),
-                content,
-            },
-            done: true,
-            total_duration: 0,
-            load_duration: 0,
-            prompt_eval_count: 0,
-            eval_count: 0,
-            eval_duration: 0,
-        };
-
-        // Log chat similar to generate: last user message and response
-        let last_user_message = payload
-            .messages
-            .iter()
-            .rfind(|m| m.role == "user")
-            .map(|m| m.content.clone())
-            .unwrap_or_default();
-        debug!(
-            "\n<<< chat: {{{}}} \n>>> response {{{}}}",
-            last_user_message, resp.message.content
-        );
-
-        Ok(Json(resp).into_response())
-
-    // stream mode
-    } else {
-        // Streaming mode with logging similar to generate
-        let last_user_message = payload
-            .messages
-            .iter()
-            .rfind(|m| m.role == "user")
-            .map(|m| m.content.clone())
-            .unwrap_or_default();
-        let user_for_log = last_user_message.clone();
-        let wrapped_stream_debug = stream! {
-            let mut acc = String::new();
-            let mut s = stream;
-            while let Some(item) = s.next().await {
-                if let Ok(chunk) = &item {
-                    if !chunk.done {
-                        acc.push_str(&chunk.message.content);
-                    }
-                }
-                yield item;
-            }
-            debug!("\n<<< chat(stream): {{{}}} \n>>> response {{{}}}", user_for_log, acc);
-        };
-
-        Ok((
-            [(
-                axum::http::header::CONTENT_TYPE,
-                "application/x-ndjson".to_string(),
-            )],
-            axum::body::Body::from_stream(
-                wrapped_stream_debug
-                    .map(|obj| serde_json::to_string(&obj.unwrap()))
-                    .map_ok(|s| format!("{}\n", s)), // Transform Ok(String) -> Ok(String + \n)
-            ),
-        )
-            .into_response())
-    }
-}
-// 处理未匹配路由的函数
-async fn not_found() -> (StatusCode, String) {
-    error!("=== Unmatched Route Request ===");
-    (StatusCode::NOT_FOUND, "Endpoint not found".to_string())
+async fn method_not_allowed(method: Method, uri: Uri) -> ProxyError {
+    ProxyError::MethodNotAllowed(format!("{} {}", method, uri.path()))
 }
 #[tokio::main]
 async fn main() {
@@ -257,16 +89,27 @@ async fn main() {
 
     let state = AppState {
         providers: load_providers(&config),
+        contexts: ContextStore::new(&config.context),
+        thinking: config.thinking,
+        format_validation: config.format_validation,
     };
     let state = Arc::new(state);
     let app: Router = Router::new()
-        .route("/", get(handle_status))
-        .route("/api/tags", get(handle_tags))
-        .route("/api/generate", post(handle_generate))
-        .route("/api/chat", post(handle_chat))
+        .route("/", get(ollama_api::handle_status))
+        .route("/api/tags", get(ollama_api::handle_tags))
+        .route("/api/show", post(ollama_api::handle_show))
+        .route("/api/generate", post(ollama_api::handle_generate))
+        .route("/api/chat", post(ollama_api::handle_chat))
+        .route("/v1/models", get(openai_api::handle_models))
+        .route(
+            "/v1/chat/completions",
+            post(openai_api::handle_chat_completions),
+        )
+        .route("/v1/messages", post(anthropic_api::handle_messages))
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
         .fallback(not_found)
+        .method_not_allowed_fallback(method_not_allowed)
         .with_state(state);
     // we should not allow lan for security's sake
     let listener = tokio::net::TcpListener::bind(format!("127.0.0.1:{}", config.port))
@@ -281,7 +124,7 @@ async fn main() {
 }
 
 fn load_providers(config: &Config) -> Vec<Box<dyn Provider + Send + Sync>> {
-    let providers = config
+    config
         .providers
         .iter()
         .map(|item| {
@@ -293,23 +136,84 @@ fn load_providers(config: &Config) -> Vec<Box<dyn Provider + Send + Sync>> {
             let models = item.models.clone().unwrap_or_default();
             let models = models
                 .iter()
-                .map(|model| Model {
-                    name: model.clone(),
-                    model: map_model_name(&item.name, model),
-                    modified_at: None,
-                    size: None,
-                    digest: None,
-                    details: None,
+                .map(|entry| {
+                    let config = entry.config();
+                    Model {
+                        name: config.name.clone(),
+                        model: map_model_name(&item.name, &config.name),
+                        modified_at: None,
+                        size: None,
+                        digest: None,
+                        details: config.details.clone(),
+                        config,
+                    }
                 })
-                .collect();
+                .collect::<Vec<_>>();
+            let configured = models.clone();
             let provider: Box<dyn Provider + Send + Sync> = match item.api_type {
                 ApiType::Ollama => Box::new(OllamaProvider::new(item.url.clone(), secret, models)),
                 ApiType::Openai => Box::new(OpenAIProvider::new(item.url.clone(), secret, models)),
+                ApiType::OpenaiResponses => Box::new(OpenAIResponsesProvider::new(
+                    item.url.clone(),
+                    secret,
+                    models,
+                )),
+                ApiType::Anthropic => {
+                    Box::new(AnthropicProvider::new(item.url.clone(), secret, models))
+                }
+                ApiType::Gemini => Box::new(GeminiProvider::new(item.url.clone(), secret, models)),
+                ApiType::Azure => Box::new(OpenAIProvider::azure(
+                    item.url.clone(),
+                    secret,
+                    item.api_version.clone(),
+                    models,
+                )),
+                ApiType::Bedrock => Box::new(BedrockProvider::new(
+                    item.url.clone(),
+                    item.aws.as_ref(),
+                    models,
+                )),
+                ApiType::LlamaCpp => {
+                    Box::new(LlamaCppProvider::new(item.url.clone(), secret, models))
+                }
             };
-            provider
+            // without a model list, whatever the upstream serves is served
+            let discovery = if provider.lists_models() {
+                item.discovery
+                    .clone()
+                    .or_else(|| item.models.is_none().then(Default::default))
+            } else {
+                if item.discovery.is_some() || item.models.is_none() {
+                    warn!(
+                        "provider '{}' cannot list its upstream's models, list them in `models`",
+                        item.name
+                    );
+                }
+                None
+            };
+            match discovery {
+                Some(discovery) => Box::new(DiscoveringProvider::new(
+                    item.name.clone(),
+                    provider,
+                    configured,
+                    discovery,
+                )),
+                // Ollama's own list has the sizes and details to pass on, not more models
+                None if matches!(item.api_type, ApiType::Ollama) => {
+                    Box::new(DiscoveringProvider::new(
+                        item.name.clone(),
+                        provider,
+                        configured,
+                        DiscoveryConfig {
+                            exclude: vec!["*".to_string()],
+                            ..Default::default()
+                        },
+                    ))
+                }
+                None => provider,
+            }
         })
-        .collect();
-    providers
+        .collect()
 }
 
 fn get_config_path() -> std::path::PathBuf {
@@ -0,0 +1,217 @@
+use crate::map_model_name;
+use crate::models::{DiscoveryConfig, GenerateRequest, Message, Model, ShowResponse};
+use crate::providers::{ChatCompletionRequest, ChatEventStream, Provider, ProviderError};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A provider whose models are the configured ones plus those its upstream lists, listed again
+/// every `refresh_secs` in the background. The configured models get the size, digest and details
+/// the upstream reports. Requests are passed on to the wrapped provider
+pub struct DiscoveringProvider {
+    inner: Arc<dyn Provider + Send + Sync>,
+    models: Arc<RwLock<Vec<Model>>>,
+}
+
+impl DiscoveringProvider {
+    /// Wraps `inner` and starts listing its upstream's models; until the first listing answers,
+    /// only the configured models are served
+    pub fn new(
+        provider_name: String,
+        inner: Box<dyn Provider + Send + Sync>,
+        configured: Vec<Model>,
+        config: DiscoveryConfig,
+    ) -> Self {
+        let inner: Arc<dyn Provider + Send + Sync> = Arc::from(inner);
+        let models = Arc::new(RwLock::new(configured.clone()));
+        let refresher = Refresher {
+            provider_name,
+            inner: inner.clone(),
+            configured,
+            config,
+            models: models.clone(),
+        };
+        tokio::spawn(refresher.run());
+        Self { inner, models }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for DiscoveringProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        self.inner.chat(request)
+    }
+
+    fn generate(
+        &self,
+        model: &str,
+        request: &GenerateRequest,
+        history: &[Message],
+    ) -> Result<ChatEventStream, ProviderError> {
+        self.inner.generate(model, request, history)
+    }
+
+    async fn get_models(&self) -> Vec<Model> {
+        self.models.read().unwrap().clone()
+    }
+
+    fn lists_models(&self) -> bool {
+        self.inner.lists_models()
+    }
+
+    async fn fetch_models(&self) -> Result<Vec<Model>, ProviderError> {
+        self.inner.fetch_models().await
+    }
+
+    fn native_tools(&self) -> bool {
+        self.inner.native_tools()
+    }
+
+    async fn show(&self, model: &Model) -> Result<ShowResponse, ProviderError> {
+        self.inner.show(model).await
+    }
+}
+
+struct Refresher {
+    provider_name: String,
+    inner: Arc<dyn Provider + Send + Sync>,
+    configured: Vec<Model>,
+    config: DiscoveryConfig,
+    models: Arc<RwLock<Vec<Model>>>,
+}
+
+impl Refresher {
+    async fn run(self) {
+        loop {
+            self.refresh().await;
+            if self.config.refresh_secs == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(self.config.refresh_secs)).await;
+        }
+    }
+
+    /// Lists the upstream's models again, keeping the previous list when that fails
+    async fn refresh(&self) {
+        let discovered = match self.inner.fetch_models().await {
+            Ok(discovered) => discovered,
+            Err(e) => {
+                warn!(
+                    "listing the models of '{}' failed: {}",
+                    self.provider_name, e
+                );
+                return;
+            }
+        };
+        let mut models = self.configured.clone();
+        for mut model in discovered {
+            // the configured entry wins, it may declare capabilities; details declared there too
+            if let Some(configured) = models.iter_mut().find(|m| same_model(&m.name, &model.name)) {
+                configured.modified_at = model.modified_at;
+                configured.size = model.size;
+                configured.digest = model.digest;
+                configured.details = configured.details.take().or(model.details);
+                continue;
+            }
+            if !self.is_wanted(&model.name) {
+                continue;
+            }
+            model.model = map_model_name(&self.provider_name, &model.name);
+            models.push(model);
+        }
+        info!(
+            "'{}' serves {} models, {} of them configured",
+            self.provider_name,
+            models.len(),
+            self.configured.len()
+        );
+        *self.models.write().unwrap() = models;
+    }
+
+    fn is_wanted(&self, name: &str) -> bool {
+        let included = self.config.include.is_empty()
+            || self.config.include.iter().any(|p| glob_match(p, name));
+        included && !self.config.exclude.iter().any(|p| glob_match(p, name))
+    }
+}
+
+/// Whether a listed model is the configured one, Ollama lists `llama3` as `llama3:latest`
+fn same_model(configured: &str, listed: &str) -> bool {
+    configured == listed || listed.strip_suffix(":latest") == Some(configured)
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters and `?` for one
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was seen in the pattern, and how much of the name it took so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` take one more character
+                Some((star, taken)) => {
+                    p = star + 1;
+                    n = taken + 1;
+                    backtrack = Some((star, taken + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "llama3:8b"));
+        assert!(glob_match("llama3*", "llama3:8b"));
+        assert!(glob_match("*embed*", "nomic-embed-text:latest"));
+        assert!(!glob_match("llama3*", "qwen3:8b"));
+        assert!(!glob_match("", "llama3"));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        // `*` first takes nothing, then the `a` matched against the pattern's own
+        assert!(glob_match("*ab", "aab"));
+        assert!(glob_match(
+            "*-embed*:latest",
+            "mxbai-embed-large-embed:latest"
+        ));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("*:*b", "qwen3:30b"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(!glob_match("*:latest", "llama3:latest-q4"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("qwen?:8b", "qwen3:8b"));
+        assert!(!glob_match("qwen?:8b", "qwen:8b"));
+        assert!(glob_match("qwen3:??b", "qwen3:32b"));
+        assert!(glob_match("??", "é€"));
+    }
+
+    #[test]
+    fn latest_tag_is_the_configured_model() {
+        assert!(same_model("llama3", "llama3:latest"));
+        assert!(same_model("llama3:8b", "llama3:8b"));
+        assert!(!same_model("llama3", "llama3:8b"));
+        assert!(!same_model("llama3:latest", "llama3"));
+    }
+}
@@ -1,23 +1,223 @@
+use super::ModelDetails;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub port: i16,
     pub providers: Vec<ProviderInfo>,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub thinking: ThinkingConfig,
+    // check answers to requests with a `format`, off when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_validation: Option<FormatValidationConfig>,
 }
+
+/// How long the conversations behind `/api/generate` `context` handles are kept
+#[derive(Serialize, Deserialize)]
+pub struct ContextConfig {
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 30 * 60,
+            max_entries: 1000,
+        }
+    }
+}
+
+/// Proxy-side check of structured output against the requested `format`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FormatValidationConfig {
+    // how many times to ask again when the answer does not match
+    #[serde(default)]
+    pub retries: u32,
+}
+
+/// How the model's reasoning is returned to clients
+#[derive(Serialize, Deserialize, Default)]
+pub struct ThinkingConfig {
+    #[serde(default)]
+    pub mode: ThinkingMode,
+    // overrides for clients that don't understand the `thinking` field
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clients: Vec<ClientThinking>,
+}
+
+impl ThinkingConfig {
+    /// The mode for a client, picked by the first override its User-Agent contains
+    pub fn mode_for(&self, user_agent: &str) -> ThinkingMode {
+        self.clients
+            .iter()
+            .find(|c| user_agent.contains(&c.user_agent))
+            .map(|c| c.mode)
+            .unwrap_or(self.mode)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientThinking {
+    pub user_agent: String,
+    pub mode: ThinkingMode,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkingMode {
+    /// Ollama's `thinking` field
+    #[default]
+    Field,
+    /// prepended to the content as `<think>...</think>`
+    Inline,
+    /// not returned at all
+    Drop,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ProviderInfo {
     pub name: String,
     pub url: String,
     pub secret: Option<String>,
-    pub models: Option<Vec<String>>,
+    pub models: Option<Vec<ModelEntry>>,
     pub api_type: ApiType,
+    // the api-version query parameter of Azure OpenAI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    // region and credentials of AWS Bedrock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aws: Option<AwsConfig>,
+    // listing the upstream's models, on by default when `models` is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<DiscoveryConfig>,
+}
+
+/// Which of the models an upstream lists are served next to the configured ones.
+/// Patterns are globs where `*` matches any run of characters and `?` a single one
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DiscoveryConfig {
+    // seconds between two listings, 0 to list only at startup
+    #[serde(default = "DiscoveryConfig::default_refresh_secs")]
+    pub refresh_secs: u64,
+    // only keep the models matching one of these, all of them when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    // then drop the models matching one of these
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl DiscoveryConfig {
+    fn default_refresh_secs() -> u64 {
+        5 * 60
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            refresh_secs: Self::default_refresh_secs(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// AWS settings of a Bedrock provider. Without a region or keys here, they are read from the usual
+/// `AWS_REGION`, `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` variables
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AwsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+}
+
+/// A model served by a provider: either its bare name or its name with declared capabilities
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ModelEntry {
+    Name(String),
+    Config(Box<ModelConfig>),
+}
+
+impl ModelEntry {
+    pub fn config(&self) -> ModelConfig {
+        match self {
+            ModelEntry::Name(name) => ModelConfig {
+                name: name.clone(),
+                ..Default::default()
+            },
+            ModelEntry::Config(config) => (**config).clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ModelConfig {
+    pub name: String,
+    // how to serve `suffix` (fill-in-the-middle) requests, for openai-compatible providers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fim: Option<FimMode>,
+    // whether the model accepts images; unset leaves it to the upstream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<bool>,
+    // how `tools` are passed to the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_mode: Option<ToolMode>,
+    // the Azure OpenAI deployment serving the model, when it is not named after the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    // family, parameter size, ... as listed by /api/tags; Ollama upstreams report their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<ModelDetails>,
+    // the context window in tokens, reported by /api/show
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    // Ollama capabilities (`completion`, `tools`, `vision`, `insert`, `thinking`, ...) reported by
+    // /api/show, asked of Ollama upstreams or guessed from the settings above when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolMode {
+    /// the upstream's own tool calling
+    Native,
+    /// for models without tool support: the proxy describes the tools in the system prompt and
+    /// reads the calls back out of the answer
+    Emulated,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FimMode {
+    /// the legacy `/completions` endpoint, which takes `suffix` natively
+    Completions,
+    /// `/completions` with a prompt built from the model's FIM tokens,
+    /// `{prefix}` and `{suffix}` are replaced by the request's prompt and suffix
+    Template { template: String },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum ApiType {
     Ollama,
     Openai,
+    // OpenAI's /responses API
+    OpenaiResponses,
+    Anthropic,
+    Gemini,
+    Azure,
+    Bedrock,
+    LlamaCpp,
 }
 
 pub fn get_config_demo() -> String {
@@ -30,6 +230,13 @@ pub fn get_config_demo() -> String {
                 secret: None,
                 models: None,
                 api_type: ApiType::Ollama,
+                api_version: None,
+                aws: None,
+                discovery: DiscoveryConfig {
+                    exclude: vec!["*embed*".to_string()],
+                    ..Default::default()
+                }
+                .into(),
             },
             ProviderInfo {
                 name: "aliyun".to_string(),
@@ -42,10 +249,30 @@ pub fn get_config_demo() -> String {
                     "glm-4.5",
                 ]
                 .iter()
-                .map(|x| x.to_string())
+                .map(|x| ModelEntry::Name(x.to_string()))
+                .chain([ModelEntry::Config(Box::new(ModelConfig {
+                    name: "qwen2.5-coder-7b-instruct".to_string(),
+                    fim: Some(FimMode::Template {
+                        template: "<|fim_prefix|>{prefix}<|fim_suffix|>{suffix}<|fim_middle|>"
+                            .to_string(),
+                    }),
+                    vision: Some(false),
+                    tool_mode: Some(ToolMode::Emulated),
+                    deployment: None,
+                    details: Some(ModelDetails {
+                        family: "qwen2".to_string(),
+                        parameter_size: "7.6B".to_string(),
+                        ..Default::default()
+                    }),
+                    context_length: Some(32768),
+                    capabilities: None,
+                }))])
                 .collect::<Vec<_>>()
                 .into(),
                 api_type: ApiType::Openai,
+                api_version: None,
+                aws: None,
+                discovery: None,
             },
             ProviderInfo {
                 name: "openrouter".to_string(),
@@ -53,10 +280,13 @@ pub fn get_config_demo() -> String {
                 secret: "secret-key".to_string().into(),
                 models: ["anthropic/claude-sonnet-4.5", "openai/o3-pro"]
                     .iter()
-                    .map(|x| x.to_string())
+                    .map(|x| ModelEntry::Name(x.to_string()))
                     .collect::<Vec<_>>()
                     .into(),
                 api_type: ApiType::Openai,
+                api_version: None,
+                aws: None,
+                discovery: None,
             },
             ProviderInfo {
                 name: "tsinghua".to_string(),
@@ -64,12 +294,111 @@ pub fn get_config_demo() -> String {
                 secret: "secret-key".to_string().into(),
                 models: ["Qwen3-Coder-Plus", "GLM-4.5"]
                     .iter()
-                    .map(|x| x.to_string())
+                    .map(|x| ModelEntry::Name(x.to_string()))
                     .collect::<Vec<_>>()
                     .into(),
                 api_type: ApiType::Openai,
+                api_version: None,
+                aws: None,
+                discovery: None,
+            },
+            ProviderInfo {
+                name: "openai".to_string(),
+                url: "https://api.openai.com/v1".to_string(),
+                secret: "secret-key".to_string().into(),
+                models: ["gpt-5", "o4-mini"]
+                    .iter()
+                    .map(|x| ModelEntry::Name(x.to_string()))
+                    .collect::<Vec<_>>()
+                    .into(),
+                api_type: ApiType::OpenaiResponses,
+                api_version: None,
+                aws: None,
+                discovery: None,
+            },
+            ProviderInfo {
+                name: "anthropic".to_string(),
+                url: "https://api.anthropic.com".to_string(),
+                secret: "secret-key".to_string().into(),
+                models: ["claude-sonnet-4-5"]
+                    .iter()
+                    .map(|x| ModelEntry::Name(x.to_string()))
+                    .collect::<Vec<_>>()
+                    .into(),
+                api_type: ApiType::Anthropic,
+                api_version: None,
+                aws: None,
+                discovery: None,
+            },
+            ProviderInfo {
+                name: "gemini".to_string(),
+                url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+                secret: "secret-key".to_string().into(),
+                models: ["gemini-2.5-pro", "gemini-2.5-flash"]
+                    .iter()
+                    .map(|x| ModelEntry::Name(x.to_string()))
+                    .collect::<Vec<_>>()
+                    .into(),
+                api_type: ApiType::Gemini,
+                api_version: None,
+                aws: None,
+                discovery: None,
+            },
+            ProviderInfo {
+                name: "azure".to_string(),
+                url: "https://some-resource.openai.azure.com".to_string(),
+                secret: "secret-key".to_string().into(),
+                models: vec![ModelEntry::Config(Box::new(ModelConfig {
+                    name: "gpt-4o".to_string(),
+                    deployment: Some("gpt-4o-prod".to_string()),
+                    ..Default::default()
+                }))]
+                .into(),
+                api_type: ApiType::Azure,
+                api_version: Some("2024-10-21".to_string()),
+                aws: None,
+                discovery: None,
+            },
+            ProviderInfo {
+                name: "bedrock".to_string(),
+                url: "https://bedrock-runtime.us-east-1.amazonaws.com".to_string(),
+                secret: None,
+                models: [
+                    "anthropic.claude-3-5-haiku-20241022-v1:0",
+                    "amazon.nova-pro-v1:0",
+                ]
+                .iter()
+                .map(|x| ModelEntry::Name(x.to_string()))
+                .collect::<Vec<_>>()
+                .into(),
+                api_type: ApiType::Bedrock,
+                api_version: None,
+                aws: Some(AwsConfig {
+                    region: Some("us-east-1".to_string()),
+                    ..Default::default()
+                }),
+                discovery: None,
+            },
+            ProviderInfo {
+                name: "llamacpp".to_string(),
+                url: "http://127.0.0.1:8080".to_string(),
+                secret: None,
+                models: vec![ModelEntry::Name("qwen2.5-coder-7b".to_string())].into(),
+                api_type: ApiType::LlamaCpp,
+                api_version: None,
+                aws: None,
+                discovery: None,
             },
         ],
+        context: ContextConfig::default(),
+        thinking: ThinkingConfig {
+            mode: ThinkingMode::Field,
+            clients: vec![ClientThinking {
+                user_agent: "JetBrains".to_string(),
+                mode: ThinkingMode::Inline,
+            }],
+        },
+        format_validation: None,
     };
     serde_yaml::to_string(&config).unwrap()
 }
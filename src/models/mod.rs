@@ -11,9 +11,32 @@ pub struct Model {
     pub size: Option<u64>,
     pub digest: Option<String>,
     pub details: Option<ModelDetails>,
+    // capabilities declared in the config file
+    #[serde(skip)]
+    pub config: ModelConfig,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl Model {
+    /// A model as an upstream lists it, known by its name only
+    pub fn named(name: String) -> Self {
+        Model {
+            model: name.clone(),
+            config: ModelConfig {
+                name: name.clone(),
+                ..Default::default()
+            },
+            name,
+            modified_at: None,
+            size: None,
+            digest: None,
+            details: None,
+        }
+    }
+}
+
+// also declared in the config file, where any of it may be left out
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
 pub struct ModelDetails {
     pub format: String,
     pub family: String,
@@ -22,10 +45,72 @@ pub struct ModelDetails {
     pub quantization_level: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Message {
     pub role: String,
     pub content: String,
+    // the model's reasoning, on assistant messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    // base64 encoded images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    // calls requested by the assistant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    // on `tool` messages: the function whose result this is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+    // on `tool` messages: the call this answers, when the client tracks ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub function: ToolCallFunction,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ToolCallFunction {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub name: String,
+    // a json object
+    pub arguments: serde_json::Value,
+}
+
+impl Message {
+    pub fn new(role: &str, content: String) -> Self {
+        Self {
+            role: role.to_string(),
+            content,
+            ..Default::default()
+        }
+    }
+}
+
+/// The `think` flag: reasoning on or off, or a reasoning effort ("low", "medium", "high")
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Think {
+    Enabled(bool),
+    Effort(String),
+}
+
+impl Think {
+    /// The reasoning token budget for upstreams that take one instead of an effort, none when
+    /// reasoning is off
+    pub fn budget_tokens(&self) -> Option<i64> {
+        match self {
+            Think::Enabled(false) => None,
+            Think::Effort(effort) if effort == "low" => Some(1024),
+            Think::Effort(effort) if effort == "high" => Some(16384),
+            _ => Some(4096),
+        }
+    }
 }
 
 #[derive(Deserialize,Serialize)]
@@ -33,29 +118,173 @@ pub struct ModelsResponse {
     pub models: Vec<Model>,
 }
 
+#[derive(Deserialize)]
+pub struct ShowRequest {
+    // older clients send `name`
+    #[serde(alias = "name")]
+    pub model: String,
+}
+
+// also what Ollama upstreams answer, whose other fields (template, parameters, ...) are kept as is
+#[derive(Serialize, Deserialize)]
+pub struct ShowResponse {
+    #[serde(default)]
+    pub details: ModelDetails,
+    // GGUF-style metadata, of which clients read `<architecture>.context_length`
+    #[serde(default)]
+    pub model_info: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<String>,
+    #[serde(flatten)]
+    pub rest: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ShowResponse {
+    /// What the proxy can vouch for about a model it has no upstream to ask: `tools` when the
+    /// provider passes them on (`native_tools`) or the model emulates them, `vision` and `insert`
+    /// when configured, and what the config declares
+    pub fn declared(model: &Model, native_tools: bool) -> Self {
+        let config = &model.config;
+        let mut capabilities = vec!["completion".to_string()];
+        if native_tools {
+            capabilities.push("tools".to_string());
+        }
+        if config.vision == Some(true) {
+            capabilities.push("vision".to_string());
+        }
+        if config.fim.is_some() {
+            capabilities.push("insert".to_string());
+        }
+        let details = model.details.clone().unwrap_or_default();
+        let mut model_info = serde_json::Map::new();
+        if !details.family.is_empty() {
+            model_info.insert("general.architecture".to_string(), details.family.clone().into());
+        }
+        let mut show = ShowResponse {
+            details,
+            model_info,
+            capabilities,
+            modified_at: model.modified_at.clone(),
+            rest: serde_json::Map::new(),
+        };
+        show.apply_config(config);
+        show
+    }
+
+    /// Replaces what the config declares: details, capabilities and the context window. Models
+    /// set to emulate tools take them whatever the upstream says
+    pub fn apply_config(&mut self, config: &ModelConfig) {
+        if let Some(details) = &config.details {
+            self.details = details.clone();
+        }
+        if let Some(capabilities) = &config.capabilities {
+            self.capabilities = capabilities.clone();
+        } else if config.tool_mode == Some(ToolMode::Emulated)
+            && !self.capabilities.iter().any(|c| c == "tools")
+        {
+            self.capabilities.push("tools".to_string());
+        }
+        if let Some(context_length) = config.context_length {
+            let architecture = self
+                .model_info
+                .get("general.architecture")
+                .and_then(|a| a.as_str())
+                .unwrap_or("general")
+                .to_string();
+            self.model_info.insert(
+                format!("{}.context_length", architecture),
+                context_length.into(),
+            );
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct GenerateRequest {
     pub model: String,
+    #[serde(default)]
     pub prompt: String,
-    #[allow(dead_code)]
+    pub suffix: Option<String>,
+    pub system: Option<String>,
+    pub template: Option<String>,
+    #[serde(default)]
+    pub raw: bool,
+    pub images: Option<Vec<String>>,
+    // handle returned by a previous response, see ContextStore
+    pub context: Option<Vec<i32>>,
+    // "json" or a json schema
+    pub format: Option<serde_json::Value>,
+    // duration string ("5m") or seconds
+    pub keep_alive: Option<serde_json::Value>,
+    pub think: Option<Think>,
     pub stream: Option<bool>,
-    pub options: Option<serde_json::Value>,
+    pub options: Option<ModelOptions>,
 }
 
 #[derive(Deserialize)]
 pub struct ChatRequest {
     pub model: String,
     pub messages: Vec<Message>,
+    // function definitions, the same shape in Ollama and OpenAI
+    #[serde(default)]
+    pub tools: Vec<serde_json::Value>,
+    // "json" or a json schema
+    pub format: Option<serde_json::Value>,
+    pub think: Option<Think>,
     #[allow(dead_code)]
     pub stream: Option<bool>,
-    pub options: Option<serde_json::Value>,
+    pub options: Option<ModelOptions>,
+}
+
+/// Ollama's generation `options`. The ones with an OpenAI equivalent are typed, the rest (num_ctx,
+/// repeat_penalty, ...) are kept as they came
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ModelOptions {
+    // maximum number of tokens to generate, -1 for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
+
 #[derive(Deserialize,Serialize)]
 pub struct StreamChatChunk {
     pub model: String,
     pub message: Message,
     pub created_at: String,
     pub done: bool,
+    // why generation stopped: stop, length, tool_calls or content_filter; only on the final chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    // only on the final chunk
+    #[serde(flatten)]
+    pub metrics: Option<Metrics>,
+}
+
+/// Ollama's timings (in nanoseconds) and token counters
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Metrics {
+    pub total_duration: u64,
+    pub load_duration: u64,
+    pub prompt_eval_count: u64,
+    pub prompt_eval_duration: u64,
+    pub eval_count: u64,
+    pub eval_duration: u64,
 }
 
 
@@ -64,13 +293,16 @@ pub struct GenerateResponse {
     pub model: String,
     pub created_at: String,
     pub response: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
     pub done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub context: Option<Vec<i32>>,
-    pub total_duration: u64,
-    pub load_duration: u64,
-    pub prompt_eval_count: u64,
-    pub eval_count: u64,
-    pub eval_duration: u64,
+    // only on the final chunk
+    #[serde(flatten)]
+    pub metrics: Option<Metrics>,
 }
 
 #[derive(Serialize)]
@@ -79,9 +311,8 @@ pub struct ChatResponse {
     pub created_at: String,
     pub message: Message,
     pub done: bool,
-    pub total_duration: u64,
-    pub load_duration: u64,
-    pub prompt_eval_count: u64,
-    pub eval_count: u64,
-    pub eval_duration: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(flatten)]
+    pub metrics: Metrics,
 }
@@ -0,0 +1,542 @@
+use crate::models::{Message, Metrics, Model, ModelOptions, Think};
+use crate::providers::{
+    arguments_object, check_images, http_client, response_lines, send, sniff_image_mime,
+    ChatCompletionRequest, ChatEvent, ChatEventStream, Provider, ProviderError, ProviderErrorKind,
+    StopReason, ToolCallDelta, ToolCallIds,
+};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tracing::warn;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// Anthropic requires max_tokens, used when the client sets no num_predict
+const DEFAULT_MAX_TOKENS: i64 = 4096;
+
+#[derive(Clone)]
+pub struct AnthropicProvider {
+    key: String,
+    models: Vec<Model>,
+    base_url: String,
+}
+
+// the server-sent events of /v1/messages, by their `type`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<Usage>,
+    },
+    MessageStop,
+    Error {
+        error: ErrorBody,
+    },
+    // ping, content_block_stop
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct MessageStart {
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    ToolUse {
+        id: String,
+        name: String,
+    },
+    // text and thinking blocks start empty, their content comes in deltas
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    // signature_delta
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(base_url: String, key: String, models: Vec<Model>) -> Self {
+        Self {
+            key,
+            base_url,
+            models,
+        }
+    }
+
+    /// Message content: plain text, or content blocks when the message carries images
+    fn build_content(message: &Message) -> Value {
+        let images = match &message.images {
+            Some(images) if !images.is_empty() => images,
+            _ => return json!(message.content),
+        };
+        let mut blocks: Vec<Value> = images
+            .iter()
+            .map(|image| {
+                if image.starts_with("http") {
+                    json!({ "type": "image", "source": { "type": "url", "url": image } })
+                } else {
+                    json!({
+                        "type": "image",
+                        "source": {
+                            "type": "base64",
+                            "media_type": sniff_image_mime(image),
+                            "data": image,
+                        },
+                    })
+                }
+            })
+            .collect();
+        blocks.push(json!({ "type": "text", "text": message.content }));
+        json!(blocks)
+    }
+
+    /// Translates Ollama-style messages. System messages go to the top-level `system` field, tool
+    /// calls become `tool_use` blocks and tool results `tool_result` blocks of a user turn
+    fn build_messages(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+        let mut system = Vec::new();
+        let mut ids = ToolCallIds::default();
+        let mut msgs: Vec<Value> = Vec::with_capacity(messages.len());
+        for (i, m) in messages.iter().enumerate() {
+            match (m.role.as_str(), &m.tool_calls) {
+                ("system", _) => system.push(m.content.clone()),
+                ("assistant", Some(tool_calls)) => {
+                    let mut blocks = Vec::new();
+                    if !m.content.is_empty() {
+                        blocks.push(json!({ "type": "text", "text": m.content }));
+                    }
+                    for (j, call) in tool_calls.iter().enumerate() {
+                        let input = arguments_object(&call.function.arguments);
+                        blocks.push(json!({
+                            "type": "tool_use",
+                            "id": ids.call(i, j, call),
+                            "name": call.function.name,
+                            "input": input,
+                        }));
+                    }
+                    msgs.push(json!({ "role": "assistant", "content": blocks }));
+                }
+                ("tool", _) => {
+                    let result = json!({
+                        "type": "tool_result",
+                        "tool_use_id": ids.result(m),
+                        "content": m.content,
+                    });
+                    // the results of parallel calls go in the same user turn
+                    match msgs.last_mut() {
+                        Some(last)
+                            if last["role"] == "user"
+                                && last["content"][0]["type"] == "tool_result" =>
+                        {
+                            if let Some(blocks) = last["content"].as_array_mut() {
+                                blocks.push(result);
+                            }
+                        }
+                        _ => msgs.push(json!({ "role": "user", "content": [result] })),
+                    }
+                }
+                _ => msgs.push(json!({ "role": m.role, "content": Self::build_content(m) })),
+            }
+        }
+        let system = (!system.is_empty()).then(|| system.join("\n\n"));
+        (system, msgs)
+    }
+
+    /// Anthropic takes the sampling options and `top_k`, but no seed or penalties
+    fn apply_options(body: &mut Value, options: Option<&ModelOptions>) {
+        let Some(options) = options else {
+            return;
+        };
+        if let Some(num_predict) = options.num_predict
+            && num_predict > 0
+        {
+            body["max_tokens"] = json!(num_predict);
+        }
+        let mapped = [
+            ("stop_sequences", options.stop.as_ref().map(|v| json!(v))),
+            ("top_p", options.top_p.map(|v| json!(v))),
+            ("temperature", options.temperature.map(|v| json!(v))),
+        ];
+        for (k, v) in mapped {
+            if let Some(v) = v {
+                body[k] = v;
+            }
+        }
+        let unmapped = [
+            ("seed", options.seed.is_some()),
+            ("presence_penalty", options.presence_penalty.is_some()),
+            ("frequency_penalty", options.frequency_penalty.is_some()),
+        ];
+        for (key, set) in unmapped {
+            if set {
+                warn!("option '{}' has no Anthropic equivalent, ignoring it", key);
+            }
+        }
+        for (key, value) in &options.extra {
+            match key.as_str() {
+                "top_k" => body["top_k"] = value.clone(),
+                _ => warn!("option '{}' has no Anthropic equivalent, ignoring it", key),
+            }
+        }
+    }
+
+    fn build_request_body(&self, request: &ChatCompletionRequest) -> Value {
+        let (system, messages) = Self::build_messages(&request.messages);
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": DEFAULT_MAX_TOKENS,
+            "stream": true,
+        });
+        if let Some(system) = system {
+            body["system"] = json!(system);
+        }
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    let function = tool.get("function").unwrap_or(tool);
+                    json!({
+                        "name": function["name"],
+                        "description": function["description"].as_str().unwrap_or_default(),
+                        "input_schema": function
+                            .get("parameters")
+                            .cloned()
+                            .unwrap_or_else(|| json!({ "type": "object" })),
+                    })
+                })
+                .collect();
+            body["tools"] = json!(tools);
+        }
+        if request.format.is_some() {
+            warn!("'format' is not supported by Anthropic, ignoring it");
+        }
+
+        Self::apply_options(&mut body, request.options.as_ref());
+
+        // extended thinking takes a token budget, which must fit in max_tokens, and does not
+        // allow changing temperature or top_k
+        let budget = request.think.as_ref().and_then(Think::budget_tokens);
+        if let Some(budget) = budget {
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+            if body["max_tokens"].as_i64().unwrap_or_default() <= budget {
+                body["max_tokens"] = json!(budget + DEFAULT_MAX_TOKENS);
+            }
+            if let Some(obj) = body.as_object_mut() {
+                obj.remove("temperature");
+                obj.remove("top_k");
+            }
+        }
+
+        body
+    }
+
+    fn build_request(
+        &self,
+        url: &str,
+        body: &Value,
+    ) -> Result<reqwest::RequestBuilder, ProviderError> {
+        let client = http_client()?;
+
+        let builder = client
+            .post(url)
+            .header("x-api-key", &self.key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("Content-Type", "application/json")
+            .json(body);
+
+        Ok(builder)
+    }
+
+    /// Sends a streaming request to the Messages API and reads its server-sent events
+    fn stream_request(
+        &self,
+        request_url: String,
+        body: &Value,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let request = self.build_request(&request_url, body)?;
+
+        let stream = async_stream::stream! {
+            let response = match send(request, &request_url).await {
+                Ok(response) => response,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+            let mut events = Box::pin(message_events(response, request_url));
+            while let Some(event) = events.next().await {
+                yield event;
+            }
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Reads the server-sent events of a /v1/messages answer
+fn message_events(
+    response: reqwest::Response,
+    request_url: String,
+) -> impl Stream<Item = ChatEvent> + Send {
+    async_stream::stream! {
+        let mut lines = Box::pin(response_lines(response, request_url.clone()));
+        let mut metrics = Metrics::default();
+        let mut done_reason = None;
+        // content block index -> tool call index
+        let mut tool_calls: HashMap<usize, usize> = HashMap::new();
+
+        // the event names are repeated in the data's `type`
+        while let Some(line) = lines.next().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+            let Some(data) = line.strip_prefix("data:") else {
+                continue;
+            };
+            match serde_json::from_str::<StreamEvent>(data.trim()) {
+                Ok(StreamEvent::MessageStart { message }) => {
+                    if let Some(usage) = message.usage {
+                        metrics.prompt_eval_count = usage.input_tokens;
+                    }
+                }
+                Ok(StreamEvent::ContentBlockStart { index, content_block: ContentBlock::ToolUse { id, name } }) => {
+                    let tool_index = tool_calls.len();
+                    tool_calls.insert(index, tool_index);
+                    yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                        index: tool_index,
+                        id: Some(id),
+                        name: Some(name),
+                        arguments: String::new(),
+                    });
+                }
+                Ok(StreamEvent::ContentBlockDelta { index, delta }) => match delta {
+                    BlockDelta::TextDelta { text } => yield ChatEvent::ContentDelta(text),
+                    BlockDelta::ThinkingDelta { thinking } => yield ChatEvent::ReasoningDelta(thinking),
+                    BlockDelta::InputJsonDelta { partial_json } => {
+                        if let Some(tool_index) = tool_calls.get(&index) {
+                            yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                index: *tool_index,
+                                arguments: partial_json,
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    BlockDelta::Other => {}
+                },
+                Ok(StreamEvent::MessageDelta { delta, usage }) => {
+                    if let Some(reason) = delta.stop_reason {
+                        done_reason = Some(StopReason::from_upstream(&reason));
+                    }
+                    if let Some(usage) = usage {
+                        metrics.eval_count = usage.output_tokens;
+                    }
+                }
+                Ok(StreamEvent::MessageStop) => {
+                    yield ChatEvent::Usage(metrics);
+                    yield ChatEvent::Done(done_reason);
+                    return;
+                }
+                // a failure after the response started, such as overloaded_error
+                Ok(StreamEvent::Error { error }) => {
+                    let status = if error.kind == "overloaded_error" { 529 } else { 500 };
+                    yield ChatEvent::Error(ProviderError {
+                        kind: ProviderErrorKind::Status(status),
+                        message: format!("{}: {}", error.kind, error.message),
+                        request_url: Some(request_url.clone()),
+                    });
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                    return;
+                }
+            }
+        }
+
+        // the stream ended without message_stop
+        yield ChatEvent::Usage(metrics);
+        yield ChatEvent::Done(done_reason);
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for AnthropicProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_images(&self.models, &request.model, &request.messages)?;
+        let request_url = format!("{}/v1/messages", self.base_url.trim_end_matches('/'));
+        let body = self.build_request_body(&request);
+        self.stream_request(request_url, &body)
+    }
+
+    async fn get_models(&self) -> Vec<Model> {
+        self.models.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The events of an answer whose server-sent events are `events`, as (name, data) pairs
+    async fn events(events: &[(&str, &str)]) -> Vec<ChatEvent> {
+        let body: String = events
+            .iter()
+            .map(|(name, data)| format!("event: {}\ndata: {}\n\n", name, data))
+            .collect();
+        let response = reqwest::Response::from(axum::http::Response::new(body));
+        message_events(response, "http://upstream/v1/messages".to_string())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn tool_use_block_becomes_a_tool_call() {
+        let events = events(&[
+            (
+                "message_start",
+                r#"{"type":"message_start","message":{"usage":{"input_tokens":12,"output_tokens":1}}}"#,
+            ),
+            (
+                "content_block_start",
+                r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            ),
+            (
+                "content_block_delta",
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Checking."}}"#,
+            ),
+            ("content_block_stop", r#"{"type":"content_block_stop","index":0}"#),
+            (
+                "content_block_start",
+                r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}"#,
+            ),
+            (
+                "content_block_delta",
+                r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"city\": "}}"#,
+            ),
+            (
+                "content_block_delta",
+                r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"\"Paris\"}"}}"#,
+            ),
+            ("content_block_stop", r#"{"type":"content_block_stop","index":1}"#),
+            (
+                "message_delta",
+                r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":20}}"#,
+            ),
+            ("message_stop", r#"{"type":"message_stop"}"#),
+        ])
+        .await;
+        match events.as_slice() {
+            [ChatEvent::ContentDelta(text), ChatEvent::ToolCallDelta(start), ChatEvent::ToolCallDelta(first), ChatEvent::ToolCallDelta(second), ChatEvent::Usage(metrics), ChatEvent::Done(Some(StopReason::ToolCalls))] =>
+            {
+                assert_eq!(text, "Checking.");
+                assert_eq!(start.index, 0);
+                assert_eq!(start.id.as_deref(), Some("toolu_1"));
+                assert_eq!(start.name.as_deref(), Some("get_weather"));
+                assert_eq!((first.index, second.index), (0, 0));
+                assert_eq!(
+                    format!("{}{}", first.arguments, second.arguments),
+                    r#"{"city": "Paris"}"#
+                );
+                assert_eq!((metrics.prompt_eval_count, metrics.eval_count), (12, 20));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_reason_comes_with_message_delta() {
+        let events = events(&[
+            (
+                "content_block_delta",
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#,
+            ),
+            (
+                "message_delta",
+                r#"{"type":"message_delta","delta":{"stop_reason":"max_tokens"},"usage":{"output_tokens":1}}"#,
+            ),
+            ("message_stop", r#"{"type":"message_stop"}"#),
+        ])
+        .await;
+        assert!(matches!(
+            events.last(),
+            Some(ChatEvent::Done(Some(StopReason::Length)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn error_event_ends_the_answer() {
+        let events = events(&[
+            (
+                "content_block_delta",
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hel"}}"#,
+            ),
+            (
+                "error",
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            ),
+        ])
+        .await;
+        match events.as_slice() {
+            [ChatEvent::ContentDelta(_), ChatEvent::Error(e)] => {
+                assert_eq!(e.kind, ProviderErrorKind::Status(529));
+                assert_eq!(e.message, "overloaded_error: Overloaded");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Signs a request with SigV4 and returns the headers to send with it, `content-type` excepted.
+/// `path` must be percent-encoded already, as it is sent; there is no query string
+pub fn sign(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    host: &str,
+    path: &str,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Vec<(String, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &amz_date[..8];
+    let payload_hash = hex::encode(Sha256::digest(body));
+
+    // canonical headers, sorted by name
+    let mut headers = vec![
+        ("content-type", "application/json".to_string()),
+        ("host", host.to_string()),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+
+    // every service but S3 encodes the already encoded path once more
+    let canonical_request = format!(
+        "POST\n{}\n\n{}\n{}\n{}",
+        uri_encode(path, true),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = [date, region, service, "aws4_request"].iter().fold(
+        format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+        |key, part| hmac(&key, part.as_bytes()),
+    );
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+    let mut signed: Vec<(String, String)> = headers
+        .into_iter()
+        .filter(|(name, _)| *name != "content-type" && *name != "host")
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    signed.push((
+        "authorization".to_string(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+    signed
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC takes keys of any length
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC key of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters, and `/` unless it is `keep_slash`
+pub fn uri_encode(s: &str, keep_slash: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A message of an event stream: its string headers (`:event-type`, `:message-type`, ...) and
+/// its payload
+pub struct EventMessage {
+    pub headers: HashMap<String, String>,
+    pub payload: Vec<u8>,
+}
+
+/// Splits an event stream into messages. Each message is framed as
+/// `total length | headers length | prelude crc | headers | payload | message crc`
+#[derive(Default)]
+pub struct EventStreamDecoder {
+    buffer: Vec<u8>,
+}
+
+impl EventStreamDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The next complete message, none until one has been fully received
+    pub fn next_message(&mut self) -> Result<Option<EventMessage>, String> {
+        if self.buffer.len() < 12 {
+            return Ok(None);
+        }
+        let total_len = read_u32(&self.buffer[0..4]) as usize;
+        let headers_len = read_u32(&self.buffer[4..8]) as usize;
+        if crc32fast::hash(&self.buffer[0..8]) != read_u32(&self.buffer[8..12]) {
+            return Err("event stream prelude checksum mismatch".to_string());
+        }
+        if total_len < 16 + headers_len {
+            return Err(format!("invalid event stream message length {}", total_len));
+        }
+        if self.buffer.len() < total_len {
+            return Ok(None);
+        }
+
+        let message: Vec<u8> = self.buffer.drain(..total_len).collect();
+        if crc32fast::hash(&message[..total_len - 4]) != read_u32(&message[total_len - 4..]) {
+            return Err("event stream message checksum mismatch".to_string());
+        }
+        let headers = parse_headers(&message[12..12 + headers_len])?;
+        let payload = message[12 + headers_len..total_len - 4].to_vec();
+        Ok(Some(EventMessage { headers, payload }))
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Reads the headers of a message, keeping the string ones
+fn parse_headers(mut bytes: &[u8]) -> Result<HashMap<String, String>, String> {
+    let truncated = || "truncated event stream header".to_string();
+    let mut headers = HashMap::new();
+    while let Some((&name_len, rest)) = bytes.split_first() {
+        let name_len = name_len as usize;
+        let (name, rest) = rest.split_at_checked(name_len).ok_or_else(truncated)?;
+        let (&value_type, rest) = rest.split_first().ok_or_else(truncated)?;
+        // the size of the value of each type, strings and byte arrays carry theirs
+        let value_len = match value_type {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            6 | 7 => {
+                let (len, _) = rest.split_at_checked(2).ok_or_else(truncated)?;
+                2 + u16::from_be_bytes([len[0], len[1]]) as usize
+            }
+            other => return Err(format!("unknown event stream header type {}", other)),
+        };
+        let (value, rest) = rest.split_at_checked(value_len).ok_or_else(truncated)?;
+        if value_type == 7 {
+            headers.insert(
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(&value[2..]).into_owned(),
+            );
+        }
+        bytes = rest;
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Encodes an event stream message with string headers
+    pub(crate) fn frame(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut header_bytes = Vec::new();
+        for (name, value) in headers {
+            header_bytes.push(name.len() as u8);
+            header_bytes.extend_from_slice(name.as_bytes());
+            header_bytes.push(7);
+            header_bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            header_bytes.extend_from_slice(value.as_bytes());
+        }
+        let total = (16 + header_bytes.len() + payload.len()) as u32;
+        let mut message = total.to_be_bytes().to_vec();
+        message.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        message.extend_from_slice(&crc32fast::hash(&message).to_be_bytes());
+        message.extend_from_slice(&header_bytes);
+        message.extend_from_slice(payload);
+        message.extend_from_slice(&crc32fast::hash(&message).to_be_bytes());
+        message
+    }
+
+    fn credentials(session_token: Option<&str>) -> Credentials {
+        Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: session_token.map(str::to_string),
+        }
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> &'a str {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap()
+    }
+
+    // the expected signatures were computed with botocore's SigV4Auth
+    #[test]
+    fn signs_like_botocore() {
+        let path = format!(
+            "/model/{}/converse-stream",
+            uri_encode("anthropic.claude-3-5-haiku-20241022-v1:0", false)
+        );
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let host = "bedrock-runtime.us-east-1.amazonaws.com";
+        let body = br#"{"messages":[]}"#;
+
+        let headers = sign(
+            &credentials(None),
+            "us-east-1",
+            "bedrock",
+            host,
+            &path,
+            body,
+            now,
+        );
+        assert_eq!(header(&headers, "x-amz-date"), "20240102T030405Z");
+        assert_eq!(
+            header(&headers, "x-amz-content-sha256"),
+            "5e4ce7b36ba37b78a5d5f9fd08e6b7b54ba6879d651aa46ec9e1d6fa24ebe30a"
+        );
+        assert_eq!(
+            header(&headers, "authorization"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240102/us-east-1/bedrock/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date, \
+             Signature=463311979a0a95df2e1d11c5e9c4e9fc16fd512cb2a68eccd2661c5b05e92166"
+        );
+
+        let headers = sign(
+            &credentials(Some("session-token")),
+            "us-east-1",
+            "bedrock",
+            host,
+            &path,
+            body,
+            now,
+        );
+        assert_eq!(header(&headers, "x-amz-security-token"), "session-token");
+        assert!(header(&headers, "authorization").ends_with(
+            "SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date;x-amz-security-token, \
+             Signature=a0168f5fc5057302274ceee4cdb7144e09a8c06fbf9ffa288646ba9cac640f1d"
+        ));
+    }
+
+    #[test]
+    fn model_ids_are_encoded_twice_in_the_canonical_uri() {
+        let encoded = uri_encode("anthropic.claude-3-5-haiku-20241022-v1:0", false);
+        assert_eq!(encoded, "anthropic.claude-3-5-haiku-20241022-v1%3A0");
+        assert_eq!(
+            uri_encode(&format!("/model/{}/converse-stream", encoded), true),
+            "/model/anthropic.claude-3-5-haiku-20241022-v1%253A0/converse-stream"
+        );
+        assert_eq!(
+            uri_encode(
+                "arn:aws:bedrock:eu-west-1:123:inference-profile/eu.claude",
+                false
+            ),
+            "arn%3Aaws%3Abedrock%3Aeu-west-1%3A123%3Ainference-profile%2Feu.claude"
+        );
+    }
+
+    #[test]
+    fn decodes_a_frame_split_across_pushes() {
+        let bytes = frame(
+            &[(":message-type", "event"), (":event-type", "messageStop")],
+            br#"{"stopReason":"end_turn"}"#,
+        );
+        let mut decoder = EventStreamDecoder::default();
+        // cut inside the prelude, then inside the payload
+        decoder.push(&bytes[..5]);
+        assert!(decoder.next_message().unwrap().is_none());
+        decoder.push(&bytes[5..bytes.len() - 7]);
+        assert!(decoder.next_message().unwrap().is_none());
+        decoder.push(&bytes[bytes.len() - 7..]);
+        let message = decoder.next_message().unwrap().unwrap();
+        assert_eq!(message.headers[":event-type"], "messageStop");
+        assert_eq!(message.payload, br#"{"stopReason":"end_turn"}"#);
+        assert!(decoder.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn decodes_consecutive_frames_from_one_push() {
+        let mut bytes = frame(&[(":event-type", "a")], b"{}");
+        bytes.extend(frame(&[(":event-type", "b")], b"{}"));
+        let mut decoder = EventStreamDecoder::default();
+        decoder.push(&bytes);
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap().headers[":event-type"],
+            "a"
+        );
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap().headers[":event-type"],
+            "b"
+        );
+    }
+
+    #[test]
+    fn rejects_a_corrupted_prelude() {
+        let mut bytes = frame(&[(":event-type", "a")], b"{}");
+        bytes[9] ^= 0xff;
+        let mut decoder = EventStreamDecoder::default();
+        decoder.push(&bytes);
+        assert_eq!(
+            decoder.next_message().err().unwrap(),
+            "event stream prelude checksum mismatch"
+        );
+    }
+
+    #[test]
+    fn rejects_a_corrupted_message() {
+        let mut bytes = frame(&[(":event-type", "a")], b"{}");
+        let last = bytes.len() - 5;
+        bytes[last] ^= 0xff;
+        let mut decoder = EventStreamDecoder::default();
+        decoder.push(&bytes);
+        assert_eq!(
+            decoder.next_message().err().unwrap(),
+            "event stream message checksum mismatch"
+        );
+    }
+}
@@ -0,0 +1,594 @@
+use crate::models::{AwsConfig, Message, Metrics, Model, ModelOptions, Think};
+use crate::providers::aws::{self, Credentials, EventMessage, EventStreamDecoder};
+use crate::providers::{
+    arguments_object, check_images, http_client, send, sniff_image_mime, ChatCompletionRequest,
+    ChatEvent, ChatEventStream, Provider, ProviderError, ProviderErrorKind, StopReason,
+    ToolCallDelta, ToolCallIds,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use tracing::warn;
+
+// output tokens added on top of the thinking budget when max_tokens leaves no room for an answer
+const ANSWER_TOKENS: i64 = 4096;
+
+#[derive(Clone)]
+pub struct BedrockProvider {
+    credentials: Option<Credentials>,
+    region: String,
+    models: Vec<Model>,
+    base_url: String,
+}
+
+// the events of ConverseStream, by their `:event-type` header
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum StreamEvent {
+    #[serde(rename_all = "camelCase")]
+    ContentBlockStart {
+        content_block_index: usize,
+        start: Option<BlockStart>,
+    },
+    #[serde(rename_all = "camelCase")]
+    ContentBlockDelta {
+        content_block_index: usize,
+        delta: BlockDelta,
+    },
+    #[serde(rename_all = "camelCase")]
+    MessageStop {
+        stop_reason: String,
+    },
+    Metadata {
+        usage: Option<Usage>,
+    },
+    // messageStart, contentBlockStop
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockStart {
+    tool_use: Option<ToolUseStart>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolUseStart {
+    tool_use_id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockDelta {
+    text: Option<String>,
+    tool_use: Option<ToolUseDelta>,
+    reasoning_content: Option<ReasoningContentDelta>,
+}
+
+#[derive(Deserialize)]
+struct ToolUseDelta {
+    input: String,
+}
+
+#[derive(Deserialize)]
+struct ReasoningContentDelta {
+    // unset on the deltas that carry the reasoning's signature
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct Exception {
+    #[serde(default)]
+    message: String,
+}
+
+impl BedrockProvider {
+    /// `base_url` is the Bedrock runtime endpoint, `https://bedrock-runtime.{region}.amazonaws.com`
+    /// or a stand-in
+    pub fn new(base_url: String, aws: Option<&AwsConfig>, models: Vec<Model>) -> Self {
+        let aws = aws.cloned().unwrap_or_default();
+        let from_env = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let region = aws
+            .region
+            .or_else(|| from_env("AWS_REGION"))
+            .or_else(|| from_env("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        // the keys go together, from the config or else from the environment
+        let from_config = aws
+            .access_key_id
+            .zip(aws.secret_access_key)
+            .map(|(id, secret)| (id, secret, aws.session_token));
+        let from_environment = || {
+            from_env("AWS_ACCESS_KEY_ID")
+                .zip(from_env("AWS_SECRET_ACCESS_KEY"))
+                .map(|(id, secret)| (id, secret, from_env("AWS_SESSION_TOKEN")))
+        };
+        let credentials = from_config.or_else(from_environment).map(
+            |(access_key_id, secret_access_key, session_token)| Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            },
+        );
+        if credentials.is_none() {
+            warn!(
+                "no AWS credentials for Bedrock at {}, its requests will fail",
+                base_url
+            );
+        }
+        Self {
+            credentials,
+            region,
+            base_url,
+            models,
+        }
+    }
+
+    /// The content blocks of a user or assistant message: its images, then its text
+    fn build_content(message: &Message) -> Vec<Value> {
+        let mut blocks: Vec<Value> = message
+            .images
+            .iter()
+            .flatten()
+            .filter_map(|image| {
+                if image.starts_with("http") {
+                    warn!(
+                        "Bedrock only takes inline images, dropping image url {}",
+                        image
+                    );
+                    return None;
+                }
+                let format = sniff_image_mime(image).trim_start_matches("image/");
+                Some(json!({ "image": { "format": format, "source": { "bytes": image } } }))
+            })
+            .collect();
+        // blank text blocks are rejected
+        if !message.content.is_empty() {
+            blocks.push(json!({ "text": message.content }));
+        }
+        blocks
+    }
+
+    /// Translates Ollama-style messages. System messages go to the top-level `system` field,
+    /// tool calls become `toolUse` blocks and tool results `toolResult` blocks of a user turn
+    fn build_messages(messages: &[Message]) -> (Vec<Value>, Vec<Value>) {
+        let mut system = Vec::new();
+        let mut ids = ToolCallIds::default();
+        let mut msgs: Vec<Value> = Vec::with_capacity(messages.len());
+        for (i, m) in messages.iter().enumerate() {
+            let (role, content) = match m.role.as_str() {
+                "system" => {
+                    system.push(json!({ "text": m.content }));
+                    continue;
+                }
+                "assistant" => {
+                    let mut content = Self::build_content(m);
+                    for (j, call) in m.tool_calls.iter().flatten().enumerate() {
+                        let input = arguments_object(&call.function.arguments);
+                        content.push(json!({
+                            "toolUse": {
+                                "toolUseId": ids.call(i, j, call),
+                                "name": call.function.name,
+                                "input": input,
+                            },
+                        }));
+                    }
+                    ("assistant", content)
+                }
+                "tool" => {
+                    let result = json!({
+                        "toolResult": {
+                            "toolUseId": ids.result(m),
+                            "content": [{ "text": m.content }],
+                        },
+                    });
+                    ("user", vec![result])
+                }
+                _ => ("user", Self::build_content(m)),
+            };
+            // roles must alternate, consecutive messages of a role are merged
+            match msgs.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(blocks) = last["content"].as_array_mut() {
+                        blocks.extend(content);
+                    }
+                }
+                _ => msgs.push(json!({ "role": role, "content": content })),
+            }
+        }
+        (system, msgs)
+    }
+
+    /// The `inferenceConfig` of the Converse API, which has no seed, penalties or `top_k`
+    fn inference_config(options: Option<&ModelOptions>) -> Value {
+        let mut config = json!({});
+        let Some(options) = options else {
+            return config;
+        };
+        if let Some(num_predict) = options.num_predict
+            && num_predict > 0
+        {
+            config["maxTokens"] = json!(num_predict);
+        }
+        let mapped = [
+            ("stopSequences", options.stop.as_ref().map(|v| json!(v))),
+            ("topP", options.top_p.map(|v| json!(v))),
+            ("temperature", options.temperature.map(|v| json!(v))),
+        ];
+        for (k, v) in mapped {
+            if let Some(v) = v {
+                config[k] = v;
+            }
+        }
+        let unmapped = [
+            ("seed", options.seed.is_some()),
+            ("presence_penalty", options.presence_penalty.is_some()),
+            ("frequency_penalty", options.frequency_penalty.is_some()),
+        ];
+        let extra = options.extra.keys().map(|key| (key.as_str(), true));
+        for (key, set) in unmapped.into_iter().chain(extra) {
+            if set {
+                warn!("option '{}' has no Bedrock equivalent, ignoring it", key);
+            }
+        }
+        config
+    }
+
+    fn build_request_body(&self, request: &ChatCompletionRequest) -> Value {
+        let (system, messages) = Self::build_messages(&request.messages);
+        let mut body = json!({ "messages": messages });
+        if !system.is_empty() {
+            body["system"] = json!(system);
+        }
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    let function = tool.get("function").unwrap_or(tool);
+                    let mut spec = json!({
+                        "name": function["name"],
+                        "inputSchema": {
+                            "json": function
+                                .get("parameters")
+                                .cloned()
+                                .unwrap_or_else(|| json!({ "type": "object" })),
+                        },
+                    });
+                    if let Some(description) = function["description"].as_str()
+                        && !description.is_empty()
+                    {
+                        spec["description"] = json!(description);
+                    }
+                    json!({ "toolSpec": spec })
+                })
+                .collect();
+            body["toolConfig"] = json!({ "tools": tools });
+        }
+        if request.format.is_some() {
+            warn!("'format' is not supported by Bedrock, ignoring it");
+        }
+
+        let mut config = Self::inference_config(request.options.as_ref());
+        let budget = request.think.as_ref().and_then(Think::budget_tokens);
+        if budget.is_some() && !is_anthropic_model(&request.model) {
+            warn!(
+                "'think' is only supported for Anthropic models on Bedrock, ignoring it for '{}'",
+                request.model
+            );
+        }
+        // Anthropic models take extended thinking as a model-specific field; the budget must fit
+        // in maxTokens and temperature cannot be changed
+        if let Some(budget) = budget.filter(|_| is_anthropic_model(&request.model)) {
+            body["additionalModelRequestFields"] =
+                json!({ "thinking": { "type": "enabled", "budget_tokens": budget } });
+            if config["maxTokens"].as_i64().unwrap_or_default() <= budget {
+                config["maxTokens"] = json!(budget + ANSWER_TOKENS);
+            }
+            if let Some(obj) = config.as_object_mut() {
+                obj.remove("temperature");
+            }
+        }
+        if config.as_object().is_some_and(|c| !c.is_empty()) {
+            body["inferenceConfig"] = config;
+        }
+
+        body
+    }
+
+    /// Builds a request signed with SigV4
+    fn build_request(
+        &self,
+        url: &str,
+        body: &Value,
+    ) -> Result<reqwest::RequestBuilder, ProviderError> {
+        let client = http_client()?;
+        let credentials = self.credentials.as_ref().ok_or_else(|| ProviderError {
+            kind: ProviderErrorKind::Internal,
+            message: "no AWS credentials configured, set aws.access_key_id and \
+                      aws.secret_access_key or AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+                .to_string(),
+            request_url: Some(url.to_string()),
+        })?;
+        let parsed = reqwest::Url::parse(url).map_err(|e| ProviderError {
+            kind: ProviderErrorKind::Internal,
+            message: format!("invalid Bedrock url: {}", e),
+            request_url: Some(url.to_string()),
+        })?;
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (host, None) => host.unwrap_or_default().to_string(),
+            (None, Some(_)) => String::new(),
+        };
+        // the signature covers the exact bytes sent
+        let body = serde_json::to_vec(body).unwrap_or_default();
+        let headers = aws::sign(
+            credentials,
+            &self.region,
+            "bedrock",
+            &host,
+            parsed.path(),
+            &body,
+            chrono::Utc::now(),
+        );
+
+        let mut builder = client
+            .post(parsed)
+            .header("Content-Type", "application/json")
+            .body(body);
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+
+        Ok(builder)
+    }
+
+    /// Sends a ConverseStream request and decodes its event stream
+    fn stream_request(
+        &self,
+        request_url: String,
+        body: &Value,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let request = self.build_request(&request_url, body)?;
+
+        let stream = async_stream::stream! {
+            let response = match send(request, &request_url).await {
+                Ok(response) => response,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+
+            let mut stream = response.bytes_stream();
+            let mut decoder = EventStreamDecoder::default();
+            let mut metrics = Metrics::default();
+            let mut done_reason = None;
+            // content block index -> tool call index
+            let mut tool_calls: HashMap<usize, usize> = HashMap::new();
+
+            while let Some(chunk_result) = stream.next().await {
+                let chunk = match chunk_result {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        yield ChatEvent::Error(ProviderError::http("Stream read error", e, &request_url));
+                        return;
+                    }
+                };
+                decoder.push(&chunk);
+
+                loop {
+                    let message = match decoder.next_message() {
+                        Ok(Some(message)) => message,
+                        Ok(None) => break,
+                        Err(e) => {
+                            yield ChatEvent::Error(ProviderError::decode(e, &request_url));
+                            return;
+                        }
+                    };
+
+                    let message_type = message.headers.get(":message-type").map(String::as_str);
+                    if message_type != Some("event") {
+                        // an exception (throttling, validation, ...) after the response started
+                        yield ChatEvent::Error(exception_error(&message, &request_url));
+                        return;
+                    }
+
+                    // the event type travels in a header, it is moved into the payload to pick
+                    // the variant
+                    let event_type = message.headers.get(":event-type").cloned().unwrap_or_default();
+                    let event = serde_json::from_slice::<Value>(&message.payload).and_then(|mut event| {
+                        event["type"] = json!(event_type);
+                        serde_json::from_value::<StreamEvent>(event)
+                    });
+                    match event {
+                        Ok(StreamEvent::ContentBlockStart { content_block_index, start }) => {
+                            if let Some(tool_use) = start.and_then(|s| s.tool_use) {
+                                let tool_index = tool_calls.len();
+                                tool_calls.insert(content_block_index, tool_index);
+                                yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                    index: tool_index,
+                                    id: Some(tool_use.tool_use_id),
+                                    name: Some(tool_use.name),
+                                    arguments: String::new(),
+                                });
+                            }
+                        }
+                        Ok(StreamEvent::ContentBlockDelta { content_block_index, delta }) => {
+                            if let Some(text) = delta.text {
+                                yield ChatEvent::ContentDelta(text);
+                            }
+                            if let Some(text) = delta.reasoning_content.and_then(|r| r.text) {
+                                yield ChatEvent::ReasoningDelta(text);
+                            }
+                            if let Some(tool_use) = delta.tool_use
+                                && let Some(tool_index) = tool_calls.get(&content_block_index)
+                            {
+                                yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                    index: *tool_index,
+                                    arguments: tool_use.input,
+                                    ..Default::default()
+                                });
+                            }
+                        }
+                        Ok(StreamEvent::MessageStop { stop_reason }) => {
+                            done_reason = Some(StopReason::from_upstream(&stop_reason));
+                        }
+                        // usage comes last, after messageStop
+                        Ok(StreamEvent::Metadata { usage }) => {
+                            if let Some(usage) = usage {
+                                metrics.prompt_eval_count = usage.input_tokens;
+                                metrics.eval_count = usage.output_tokens;
+                            }
+                        }
+                        Ok(StreamEvent::Other) => {}
+                        Err(e) => {
+                            yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                            return;
+                        }
+                    }
+                }
+            }
+
+            yield ChatEvent::Usage(metrics);
+            yield ChatEvent::Done(done_reason);
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+/// The error an exception message of the event stream reports
+fn exception_error(message: &EventMessage, request_url: &str) -> ProviderError {
+    let kind = message
+        .headers
+        .get(":exception-type")
+        .or_else(|| message.headers.get(":error-code"))
+        .cloned()
+        .unwrap_or_default();
+    let text = serde_json::from_slice::<Exception>(&message.payload)
+        .map(|e| e.message)
+        .ok()
+        .or_else(|| message.headers.get(":error-message").cloned())
+        .unwrap_or_default();
+    ProviderError {
+        kind: ProviderErrorKind::Status(exception_status(&kind)),
+        message: format!("{}: {}", kind, text),
+        request_url: Some(request_url.to_string()),
+    }
+}
+
+/// The HTTP status matching a Bedrock exception type
+fn exception_status(kind: &str) -> u16 {
+    match kind {
+        "validationException" => 400,
+        "accessDeniedException" => 403,
+        "resourceNotFoundException" => 404,
+        "modelTimeoutException" => 408,
+        "throttlingException" => 429,
+        "serviceUnavailableException" => 503,
+        _ => 500,
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for BedrockProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_images(&self.models, &request.model, &request.messages)?;
+        // model ids contain `:` and inference profile ARNs `/`
+        let request_url = format!(
+            "{}/model/{}/converse-stream",
+            self.base_url.trim_end_matches('/'),
+            aws::uri_encode(&request.model, false)
+        );
+        let body = self.build_request_body(&request);
+        self.stream_request(request_url, &body)
+    }
+
+    async fn get_models(&self) -> Vec<Model> {
+        self.models.clone()
+    }
+}
+
+/// Whether a model id, or the inference profile an ARN ends with, is one of Anthropic's:
+/// `anthropic.claude-...` or a cross-region `eu.anthropic.claude-...`
+fn is_anthropic_model(model: &str) -> bool {
+    let id = model.rsplit('/').next().unwrap_or(model);
+    id.starts_with("anthropic.") || id.contains(".anthropic.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::aws::tests::frame;
+
+    #[test]
+    fn exception_frame_keeps_its_status() {
+        let mut decoder = EventStreamDecoder::default();
+        decoder.push(&frame(
+            &[
+                (":message-type", "exception"),
+                (":exception-type", "throttlingException"),
+                (":content-type", "application/json"),
+            ],
+            br#"{"message":"Too many requests"}"#,
+        ));
+        let message = decoder.next_message().unwrap().unwrap();
+        let error = exception_error(&message, "http://bedrock/model/m/converse-stream");
+        assert_eq!(error.kind, ProviderErrorKind::Status(429));
+        assert_eq!(error.message, "throttlingException: Too many requests");
+    }
+
+    #[test]
+    fn unknown_exception_is_a_server_error() {
+        assert_eq!(exception_status("validationException"), 400);
+        assert_eq!(exception_status("modelStreamErrorException"), 500);
+    }
+
+    fn thinking_request(model: &str) -> Value {
+        let provider = BedrockProvider::new("http://bedrock".to_string(), None, Vec::new());
+        provider.build_request_body(&ChatCompletionRequest {
+            model: model.to_string(),
+            think: Some(Think::Enabled(true)),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn thinking_is_only_asked_of_anthropic_models() {
+        for model in [
+            "anthropic.claude-3-7-sonnet-20250219-v1:0",
+            "eu.anthropic.claude-3-7-sonnet-20250219-v1:0",
+            "arn:aws:bedrock:eu-west-1:123:inference-profile/eu.anthropic.claude-3-7-sonnet-20250219-v1:0",
+        ] {
+            let body = thinking_request(model);
+            assert_eq!(
+                body["additionalModelRequestFields"]["thinking"]["type"],
+                "enabled",
+                "{}",
+                model
+            );
+        }
+        for model in ["amazon.nova-pro-v1:0", "meta.llama3-70b-instruct-v1:0"] {
+            let body = thinking_request(model);
+            assert!(
+                body.get("additionalModelRequestFields").is_none(),
+                "{}",
+                model
+            );
+            assert!(body.get("inferenceConfig").is_none(), "{}", model);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::models::{Message, Metrics, Model, ModelOptions, Think};
+use crate::providers::{
+    arguments_object, check_images, http_client, response_lines, send, sniff_image_mime,
+    ChatCompletionRequest, ChatEvent, ChatEventStream, Provider, ProviderError, ProviderErrorKind,
+    StopReason, ToolCallDelta,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct GeminiProvider {
+    key: String,
+    models: Vec<Model>,
+    base_url: String,
+}
+
+// a chunk of streamGenerateContent
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamChunk {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    usage_metadata: Option<UsageMetadata>,
+    // set instead of candidates when the prompt itself was blocked
+    prompt_feedback: Option<PromptFeedback>,
+    error: Option<ErrorBody>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<Content>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    text: Option<String>,
+    // the part is a thought summary rather than answer text
+    #[serde(default)]
+    thought: bool,
+    function_call: Option<FunctionCall>,
+}
+
+#[derive(Deserialize)]
+struct FunctionCall {
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+    #[serde(default)]
+    thoughts_token_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: u16,
+    message: String,
+}
+
+impl GeminiProvider {
+    pub fn new(base_url: String, key: String, models: Vec<Model>) -> Self {
+        Self {
+            key,
+            base_url,
+            models,
+        }
+    }
+
+    /// The parts of a user or assistant message: its images, then its text
+    fn build_parts(message: &Message) -> Vec<Value> {
+        let mut parts: Vec<Value> = message
+            .images
+            .iter()
+            .flatten()
+            .filter_map(|image| {
+                if image.starts_with("http") {
+                    warn!(
+                        "Gemini only takes inline images, dropping image url {}",
+                        image
+                    );
+                    return None;
+                }
+                Some(json!({
+                    "inlineData": { "mimeType": sniff_image_mime(image), "data": image },
+                }))
+            })
+            .collect();
+        if !message.content.is_empty() {
+            parts.push(json!({ "text": message.content }));
+        }
+        parts
+    }
+
+    /// Translates Ollama-style messages into `contents`. System messages go to
+    /// `systemInstruction`, the assistant is the `model` and tool results are `functionResponse`
+    /// parts of a user turn
+    fn build_contents(messages: &[Message]) -> (Option<Value>, Vec<Value>) {
+        let mut system = Vec::new();
+        let mut contents: Vec<Value> = Vec::with_capacity(messages.len());
+        for m in messages {
+            let (role, parts) = match m.role.as_str() {
+                "system" => {
+                    system.push(json!({ "text": m.content }));
+                    continue;
+                }
+                "assistant" => {
+                    let mut parts = Self::build_parts(m);
+                    for call in m.tool_calls.iter().flatten() {
+                        let args = arguments_object(&call.function.arguments);
+                        parts.push(json!({
+                            "functionCall": { "name": call.function.name, "args": args },
+                        }));
+                    }
+                    ("model", parts)
+                }
+                "tool" => {
+                    // the response must be an object too
+                    let response = match serde_json::from_str::<Value>(&m.content) {
+                        Ok(value @ Value::Object(_)) => value,
+                        _ => json!({ "result": m.content }),
+                    };
+                    let part = json!({
+                        "functionResponse": {
+                            "name": m.tool_name.clone().unwrap_or_default(),
+                            "response": response,
+                        },
+                    });
+                    ("user", vec![part])
+                }
+                _ => ("user", Self::build_parts(m)),
+            };
+            // Gemini wants turns to alternate, consecutive messages of a role are merged
+            match contents.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(last_parts) = last["parts"].as_array_mut() {
+                        last_parts.extend(parts);
+                    }
+                }
+                _ => contents.push(json!({ "role": role, "parts": parts })),
+            }
+        }
+        let system = (!system.is_empty()).then(|| json!({ "parts": system }));
+        (system, contents)
+    }
+
+    /// The `generationConfig`: Gemini takes every Ollama option OpenAI does, and `top_k`
+    fn generation_config(options: Option<&ModelOptions>) -> Value {
+        let mut config = json!({});
+        let Some(options) = options else {
+            return config;
+        };
+        if let Some(num_predict) = options.num_predict
+            && num_predict >= 0
+        {
+            config["maxOutputTokens"] = json!(num_predict);
+        }
+        let mapped = [
+            ("stopSequences", options.stop.as_ref().map(|v| json!(v))),
+            ("seed", options.seed.map(|v| json!(v))),
+            ("topP", options.top_p.map(|v| json!(v))),
+            ("temperature", options.temperature.map(|v| json!(v))),
+            (
+                "presencePenalty",
+                options.presence_penalty.map(|v| json!(v)),
+            ),
+            (
+                "frequencyPenalty",
+                options.frequency_penalty.map(|v| json!(v)),
+            ),
+        ];
+        for (k, v) in mapped {
+            if let Some(v) = v {
+                config[k] = v;
+            }
+        }
+        for (key, value) in &options.extra {
+            match key.as_str() {
+                "top_k" => config["topK"] = value.clone(),
+                _ => warn!("option '{}' has no Gemini equivalent, ignoring it", key),
+            }
+        }
+        config
+    }
+
+    fn build_request_body(&self, request: &ChatCompletionRequest) -> Value {
+        let (system, contents) = Self::build_contents(&request.messages);
+        let mut body = json!({ "contents": contents });
+        if let Some(system) = system {
+            body["systemInstruction"] = system;
+        }
+        if !request.tools.is_empty() {
+            let declarations: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    let function = tool.get("function").unwrap_or(tool);
+                    let mut declaration = json!({
+                        "name": function["name"],
+                        "description": function["description"].as_str().unwrap_or_default(),
+                    });
+                    if let Some(parameters) = function.get("parameters") {
+                        declaration["parameters"] = parameters.clone();
+                    }
+                    declaration
+                })
+                .collect();
+            body["tools"] = json!([{ "functionDeclarations": declarations }]);
+        }
+
+        let mut config = Self::generation_config(request.options.as_ref());
+        match &request.format {
+            Some(Value::String(s)) if s == "json" => {
+                config["responseMimeType"] = json!("application/json");
+            }
+            Some(schema @ Value::Object(_)) => {
+                config["responseMimeType"] = json!("application/json");
+                config["responseJsonSchema"] = schema.clone();
+            }
+            _ => {}
+        }
+        if let Some(budget) = request.think.as_ref().and_then(Think::budget_tokens) {
+            config["thinkingConfig"] = json!({ "thinkingBudget": budget, "includeThoughts": true });
+        }
+        if config.as_object().is_some_and(|c| !c.is_empty()) {
+            body["generationConfig"] = config;
+        }
+
+        body
+    }
+
+    fn build_request(
+        &self,
+        url: &str,
+        body: &Value,
+    ) -> Result<reqwest::RequestBuilder, ProviderError> {
+        let client = http_client()?;
+
+        let mut builder = client
+            .post(url)
+            .query(&[("alt", "sse")])
+            .header("Content-Type", "application/json")
+            .json(body);
+        // the key goes in the query rather than in the url, so it stays out of error messages
+        if !self.key.is_empty() {
+            builder = builder.query(&[("key", &self.key)]);
+        }
+
+        Ok(builder)
+    }
+
+    /// Sends a streaming request and reads its server-sent events
+    fn stream_request(
+        &self,
+        request_url: String,
+        body: &Value,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let request = self.build_request(&request_url, body)?;
+
+        let stream = async_stream::stream! {
+            let response = match send(request, &request_url).await {
+                Ok(response) => response,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+            let mut lines = Box::pin(response_lines(response, request_url.clone()));
+            let mut metrics = Metrics::default();
+            let mut done_reason = None;
+            let mut tool_call_count = 0;
+
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        yield ChatEvent::Error(e);
+                        return;
+                    }
+                };
+                let Some(data) = line.strip_prefix("data:") else {
+                    continue;
+                };
+                let chunk = match serde_json::from_str::<StreamChunk>(data.trim()) {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                        return;
+                    }
+                };
+
+                if let Some(error) = chunk.error {
+                    yield ChatEvent::Error(ProviderError {
+                        kind: ProviderErrorKind::Status(error.code),
+                        message: error.message,
+                        request_url: Some(request_url.clone()),
+                    });
+                    return;
+                }
+                if let Some(usage) = chunk.usage_metadata {
+                    metrics.prompt_eval_count = usage.prompt_token_count;
+                    metrics.eval_count = usage.candidates_token_count + usage.thoughts_token_count;
+                }
+                if let Some(reason) = chunk.prompt_feedback.and_then(|f| f.block_reason) {
+                    warn!("Gemini blocked the prompt: {}", reason);
+                    done_reason = Some(StopReason::ContentFilter);
+                }
+
+                for candidate in chunk.candidates.into_iter().take(1) {
+                    for part in candidate.content.map(|c| c.parts).unwrap_or_default() {
+                        if let Some(call) = part.function_call {
+                            // Gemini sends each call whole
+                            yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                index: tool_call_count,
+                                id: call.id,
+                                name: Some(call.name),
+                                arguments: call.args.to_string(),
+                            });
+                            tool_call_count += 1;
+                        } else if let Some(text) = part.text {
+                            if part.thought {
+                                yield ChatEvent::ReasoningDelta(text);
+                            } else {
+                                yield ChatEvent::ContentDelta(text);
+                            }
+                        }
+                    }
+                    if let Some(reason) = candidate.finish_reason {
+                        done_reason = Some(StopReason::from_upstream(&reason.to_lowercase()));
+                    }
+                }
+            }
+
+            // Gemini reports STOP after function calls
+            if tool_call_count > 0 && matches!(done_reason, None | Some(StopReason::Stop)) {
+                done_reason = Some(StopReason::ToolCalls);
+            }
+            yield ChatEvent::Usage(metrics);
+            yield ChatEvent::Done(done_reason);
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for GeminiProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_images(&self.models, &request.model, &request.messages)?;
+        let request_url = format!(
+            "{}/models/{}:streamGenerateContent",
+            self.base_url.trim_end_matches('/'),
+            request.model
+        );
+        let body = self.build_request_body(&request);
+        self.stream_request(request_url, &body)
+    }
+
+    async fn get_models(&self) -> Vec<Model> {
+        self.models.clone()
+    }
+}
@@ -0,0 +1,353 @@
+use crate::models::{GenerateRequest, Message, Metrics, Model, ModelOptions, Think};
+use crate::providers::{
+    http_client, response_lines, send, send_json, ChatCompletionRequest, ChatEvent,
+    ChatEventStream, Provider, ProviderError, ProviderErrorKind, StopReason,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct LlamaCppProvider {
+    key: String,
+    models: Vec<Model>,
+    base_url: String,
+}
+
+// the reply of /v1/models
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelId>,
+}
+
+#[derive(Deserialize)]
+struct ModelId {
+    id: String,
+}
+
+// a chunk of /completion or /infill
+#[derive(Deserialize)]
+struct CompletionChunk {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    stop: bool,
+    // eos, word (a stop string) or limit (n_predict), on the last chunk
+    stop_type: Option<String>,
+    // on the last chunk
+    timings: Option<Timings>,
+    error: Option<ErrorBody>,
+}
+
+#[derive(Deserialize)]
+struct Timings {
+    prompt_n: u64,
+    prompt_ms: f64,
+    predicted_n: u64,
+    predicted_ms: f64,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: u16,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct AppliedTemplate {
+    prompt: String,
+}
+
+impl LlamaCppProvider {
+    pub fn new(base_url: String, key: String, models: Vec<Model>) -> Self {
+        Self {
+            key,
+            base_url,
+            models,
+        }
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), endpoint)
+    }
+
+    /// Translates Ollama options into llama.cpp parameters. The sampling options llama.cpp shares
+    /// with Ollama (top_k, min_p, repeat_penalty, ...) and its own (n_probs, grammar,
+    /// cache_prompt, id_slot, ...) are passed as they are
+    fn apply_options(body: &mut Value, options: Option<&ModelOptions>) {
+        let Some(options) = options else {
+            return;
+        };
+        let mapped = [
+            ("n_predict", options.num_predict.map(|v| json!(v))),
+            ("stop", options.stop.as_ref().map(|v| json!(v))),
+            ("seed", options.seed.map(|v| json!(v))),
+            ("top_p", options.top_p.map(|v| json!(v))),
+            ("temperature", options.temperature.map(|v| json!(v))),
+            (
+                "presence_penalty",
+                options.presence_penalty.map(|v| json!(v)),
+            ),
+            (
+                "frequency_penalty",
+                options.frequency_penalty.map(|v| json!(v)),
+            ),
+        ];
+        for (k, v) in mapped {
+            if let Some(v) = v {
+                body[k] = v;
+            }
+        }
+        for (key, value) in &options.extra {
+            match key.as_str() {
+                "num_ctx" | "num_batch" | "num_gpu" | "main_gpu" | "num_thread" | "use_mmap" => {
+                    warn!(
+                        "option '{}' is set when llama-server starts, ignoring it",
+                        key
+                    )
+                }
+                "num_keep" => body["n_keep"] = value.clone(),
+                "penalize_newline" => body["penalize_nl"] = value.clone(),
+                _ => body[key.as_str()] = value.clone(),
+            }
+        }
+    }
+
+    /// The body shared by /completion and /infill, without the prompt
+    fn build_body(model: &str, format: Option<&Value>, options: Option<&ModelOptions>) -> Value {
+        let mut body = json!({ "model": model, "stream": true });
+        match format {
+            // an empty schema takes any JSON
+            Some(Value::String(s)) if s == "json" => body["json_schema"] = json!({}),
+            Some(schema @ Value::Object(_)) => body["json_schema"] = schema.clone(),
+            Some(other) => warn!("unsupported format {}, ignoring it", other),
+            None => {}
+        }
+        Self::apply_options(&mut body, options);
+        body
+    }
+
+    /// The /apply-template body rendering `messages` with the model's chat template
+    fn build_template_body(messages: &[Message], think: Option<&Think>) -> Value {
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|m| json!({ "role": m.role, "content": m.content }))
+            .collect();
+        let mut body = json!({ "messages": messages });
+        // reasoning templates (Qwen3, DeepSeek, ...) switch on this
+        if let Some(think) = think {
+            body["chat_template_kwargs"] =
+                json!({ "enable_thinking": think.budget_tokens().is_some() });
+        }
+        body
+    }
+
+    fn build_request(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        body: &Value,
+    ) -> reqwest::RequestBuilder {
+        let builder = client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .json(body);
+        self.authorize(builder)
+    }
+
+    fn build_get(&self, client: &reqwest::Client, url: &str) -> reqwest::RequestBuilder {
+        self.authorize(client.get(url))
+    }
+
+    // llama-server only checks a key when started with --api-key
+    fn authorize(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if self.key.is_empty() {
+            builder
+        } else {
+            builder.header("Authorization", format!("Bearer {}", self.key))
+        }
+    }
+
+    /// Streams a native completion. With a `template` body, the prompt is first rendered from
+    /// chat messages through /apply-template
+    fn stream_request(
+        &self,
+        request_url: String,
+        mut body: Value,
+        template: Option<Value>,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let client = http_client()?;
+        let template_url = self.url("apply-template");
+        let provider = self.clone();
+
+        let stream = async_stream::stream! {
+            if let Some(template) = template {
+                let request = provider.build_request(&client, &template_url, &template);
+                match send_json::<AppliedTemplate>(request, &template_url).await {
+                    Ok(applied) => body["prompt"] = json!(applied.prompt),
+                    Err(e) => {
+                        yield ChatEvent::Error(e);
+                        return;
+                    }
+                }
+            }
+
+            let request = provider.build_request(&client, &request_url, &body);
+            let response = match send(request, &request_url).await {
+                Ok(response) => response,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+            let mut lines = Box::pin(response_lines(response, request_url.clone()));
+            let mut done_reason = None;
+
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        yield ChatEvent::Error(e);
+                        return;
+                    }
+                };
+                let Some(data) = line.strip_prefix("data:") else {
+                    continue;
+                };
+                let chunk = match serde_json::from_str::<CompletionChunk>(data.trim()) {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                        return;
+                    }
+                };
+                if let Some(error) = chunk.error {
+                    yield ChatEvent::Error(ProviderError {
+                        kind: ProviderErrorKind::Status(error.code),
+                        message: error.message,
+                        request_url: Some(request_url.clone()),
+                    });
+                    return;
+                }
+                if !chunk.content.is_empty() {
+                    yield ChatEvent::ContentDelta(chunk.content);
+                }
+                if let Some(timings) = chunk.timings {
+                    yield ChatEvent::Usage(Metrics {
+                        prompt_eval_count: timings.prompt_n,
+                        prompt_eval_duration: (timings.prompt_ms * 1_000_000.0) as u64,
+                        eval_count: timings.predicted_n,
+                        eval_duration: (timings.predicted_ms * 1_000_000.0) as u64,
+                        ..Default::default()
+                    });
+                }
+                if chunk.stop {
+                    done_reason = Some(match chunk.stop_type.as_deref() {
+                        Some("limit") => StopReason::Length,
+                        _ => StopReason::Stop,
+                    });
+                }
+            }
+
+            yield ChatEvent::Done(done_reason);
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Rejects what llama-server's native endpoints cannot take
+fn check_request(messages: &[Message], tools: &[Value]) -> Result<(), ProviderError> {
+    if !tools.is_empty() {
+        return Err(ProviderError::invalid_request(
+            "tools are not supported by llama.cpp's native endpoints, set the model's \
+             tool_mode to emulated"
+                .to_string(),
+        ));
+    }
+    if messages
+        .iter()
+        .any(|m| m.images.as_ref().is_some_and(|images| !images.is_empty()))
+    {
+        return Err(ProviderError::invalid_request(
+            "images are not supported by llama.cpp's native endpoints".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Provider for LlamaCppProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_request(&request.messages, &request.tools)?;
+        let body = Self::build_body(
+            &request.model,
+            request.format.as_ref(),
+            request.options.as_ref(),
+        );
+        let template = Self::build_template_body(&request.messages, request.think.as_ref());
+        self.stream_request(self.url("completion"), body, Some(template))
+    }
+
+    // `suffix` goes to /infill and `raw` prompts straight to /completion, everything else is chat
+    fn generate(
+        &self,
+        model: &str,
+        request: &GenerateRequest,
+        history: &[Message],
+    ) -> Result<ChatEventStream, ProviderError> {
+        if request.suffix.is_none() && !request.raw {
+            return self.chat(ChatCompletionRequest::from_generate(
+                model, request, history,
+            )?);
+        }
+        if request.template.is_some() {
+            return Err(ProviderError::invalid_request(
+                "'template' is not supported by this model's provider".to_string(),
+            ));
+        }
+        check_request(
+            &[Message {
+                images: request.images.clone(),
+                ..Message::new("user", String::new())
+            }],
+            &[],
+        )?;
+
+        let mut body = Self::build_body(model, request.format.as_ref(), request.options.as_ref());
+        let request_url = match &request.suffix {
+            Some(suffix) => {
+                body["input_prefix"] = json!(request.prompt);
+                body["input_suffix"] = json!(suffix);
+                self.url("infill")
+            }
+            None => {
+                body["prompt"] = json!(request.prompt);
+                self.url("completion")
+            }
+        };
+        self.stream_request(request_url, body, None)
+    }
+
+    async fn get_models(&self) -> Vec<Model> {
+        self.models.clone()
+    }
+
+    fn native_tools(&self) -> bool {
+        false
+    }
+
+    fn lists_models(&self) -> bool {
+        true
+    }
+
+    // llama-server lists the model it runs through its OpenAI-compatible API
+    async fn fetch_models(&self) -> Result<Vec<Model>, ProviderError> {
+        let request_url = self.url("v1/models");
+        let client = http_client()?;
+        let request = self.build_get(&client, &request_url);
+        let list: ModelList = send_json(request, &request_url).await?;
+        Ok(list.data.into_iter().map(|m| Model::named(m.id)).collect())
+    }
+}
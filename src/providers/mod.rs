@@ -1,16 +1,93 @@
+pub mod anthropic_provider;
+pub mod aws;
+pub mod bedrock_provider;
+pub mod gemini_provider;
+pub mod llamacpp_provider;
 pub mod ollama_provider;
 pub mod openai_provider;
+pub mod openai_responses_provider;
 
-use crate::models::{Message, Model, StreamChatChunk};
+use crate::models::{
+    GenerateRequest, Message, Metrics, Model, ModelOptions, ShowResponse, Think, ToolCall,
+    ToolCallFunction,
+};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::Value;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::warn;
+
+/// Coarse classification of a provider failure, used to pick the HTTP status returned to clients
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderErrorKind {
+    /// The proxy itself failed before talking to the upstream
+    Internal,
+    /// The client asked for something this provider cannot do
+    InvalidRequest,
+    /// The upstream could not be reached
+    Connect,
+    /// The upstream did not answer in time
+    Timeout,
+    /// The upstream answered with a non-success HTTP status
+    Status(u16),
+    /// The upstream answered with something we could not decode
+    Decode,
+}
 
 #[derive(Debug, Serialize)]
 pub struct ProviderError {
+    pub kind: ProviderErrorKind,
     pub message: String,
     pub request_url: Option<String>,
 }
 
+impl ProviderError {
+    /// Wraps a reqwest error, classifying timeouts and connection failures. The url is kept
+    /// apart, out of the message clients see
+    pub fn http(context: &str, e: reqwest::Error, request_url: &str) -> Self {
+        let e = e.without_url();
+        let kind = if e.is_timeout() {
+            ProviderErrorKind::Timeout
+        } else if let Some(status) = e.status() {
+            ProviderErrorKind::Status(status.as_u16())
+        } else {
+            ProviderErrorKind::Connect
+        };
+        Self {
+            kind,
+            message: format!("{}: {}", context, e),
+            request_url: Some(request_url.to_string()),
+        }
+    }
+
+    /// Builds an error from a non-success upstream response
+    pub fn status(status: reqwest::StatusCode, body: String, request_url: &str) -> Self {
+        Self {
+            kind: ProviderErrorKind::Status(status.as_u16()),
+            message: format!("HTTP error {}: {}", status, body),
+            request_url: Some(request_url.to_string()),
+        }
+    }
+
+    pub fn invalid_request(message: String) -> Self {
+        Self {
+            kind: ProviderErrorKind::InvalidRequest,
+            message,
+            request_url: None,
+        }
+    }
+
+    pub fn decode(message: String, request_url: &str) -> Self {
+        Self {
+            kind: ProviderErrorKind::Decode,
+            message,
+            request_url: Some(request_url.to_string()),
+        }
+    }
+}
+
 impl std::fmt::Display for ProviderError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Provider error: {}", self.message)
@@ -19,22 +96,430 @@ impl std::fmt::Display for ProviderError {
 
 impl std::error::Error for ProviderError {}
 
+/// The HTTP client providers reach their upstream with, built once so that connections are
+/// reused across requests. Answers are streamed for as long as the model writes, so only an
+/// upstream that stops sending times out
+pub fn http_client() -> Result<reqwest::Client, ProviderError> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    if let Some(client) = CLIENT.get() {
+        return Ok(client.clone());
+    }
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .read_timeout(Duration::from_secs(120))
+        .build()
+        .map_err(|e| ProviderError {
+            kind: ProviderErrorKind::Internal,
+            message: format!("Failed to build HTTP client: {}", e),
+            request_url: None,
+        })?;
+    Ok(CLIENT.get_or_init(|| client).clone())
+}
+
+/// Sends a request, turning a failed send or a non-success status into an error
+pub async fn send(
+    request: reqwest::RequestBuilder,
+    request_url: &str,
+) -> Result<reqwest::Response, ProviderError> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| ProviderError::http("HTTP request failed", e, request_url))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(ProviderError::status(status, error_text, request_url));
+    }
+    Ok(response)
+}
+
+/// Sends a request and decodes its JSON reply
+pub async fn send_json<T: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    request_url: &str,
+) -> Result<T, ProviderError> {
+    send(request, request_url)
+        .await?
+        .json()
+        .await
+        .map_err(|e| ProviderError::decode(format!("JSON parse error: {}", e), request_url))
+}
+
+/// The non-blank lines of a streamed reply (NDJSON, server-sent events), trimmed. Lines are
+/// decoded once complete, a character may be split across network chunks
+pub fn response_lines(
+    response: reqwest::Response,
+    request_url: String,
+) -> impl Stream<Item = Result<String, ProviderError>> + Send {
+    async_stream::stream! {
+        let mut stream = response.bytes_stream();
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ended = false;
+        while !ended {
+            match stream.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    yield Err(ProviderError::http("Stream read error", e, &request_url));
+                    return;
+                }
+                // whatever is left is the last line
+                None => {
+                    ended = true;
+                    buffer.push(b'\n');
+                }
+            }
+            while let Some(line_end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=line_end).collect();
+                match String::from_utf8(line) {
+                    Ok(line) if line.trim().is_empty() => {}
+                    Ok(line) => yield Ok(line.trim().to_string()),
+                    Err(e) => {
+                        yield Err(ProviderError::decode(format!("UTF-8 decode error: {}", e), &request_url));
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A chat request in the proxy's internal protocol, whatever API it came in through
+#[derive(Debug, Clone, Default)]
+pub struct ChatCompletionRequest {
+    // the model name as the upstream knows it
+    pub model: String,
+    pub messages: Vec<Message>,
+    // function definitions the model may call
+    pub tools: Vec<Value>,
+    // "json" or a json schema the answer must follow
+    pub format: Option<Value>,
+    // whether, or how hard, a reasoning model should think
+    pub think: Option<Think>,
+    pub options: Option<ModelOptions>,
+}
+
+impl ChatCompletionRequest {
+    /// The chat request a generate request stands for, see `generate_messages`
+    pub fn from_generate(
+        model: &str,
+        request: &GenerateRequest,
+        history: &[Message],
+    ) -> Result<Self, ProviderError> {
+        Ok(Self {
+            model: model.to_string(),
+            messages: generate_messages(request, history)?,
+            format: request.format.clone(),
+            think: request.think.clone(),
+            options: request.options.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Why the model stopped generating
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+    // anything else an upstream reports, passed through as is
+    Other(String),
+}
+
+impl StopReason {
+    /// Reads an OpenAI `finish_reason`, an Ollama `done_reason`, an Anthropic or Bedrock
+    /// `stop_reason` or a lowercased Gemini `finishReason`
+    pub fn from_upstream(reason: &str) -> Self {
+        match reason {
+            "stop" | "end_turn" | "stop_sequence" => StopReason::Stop,
+            "length" | "max_tokens" => StopReason::Length,
+            // function_call is the legacy name of tool_calls
+            "tool_calls" | "function_call" | "tool_use" => StopReason::ToolCalls,
+            "content_filter"
+            | "refusal"
+            | "safety"
+            | "recitation"
+            | "blocklist"
+            | "prohibited_content"
+            | "spii"
+            | "guardrail_intervened"
+            | "content_filtered" => StopReason::ContentFilter,
+            other => StopReason::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            StopReason::Stop => "stop",
+            StopReason::Length => "length",
+            StopReason::ToolCalls => "tool_calls",
+            StopReason::ContentFilter => "content_filter",
+            StopReason::Other(reason) => reason,
+        }
+    }
+}
+
+/// A fragment of a tool call. Fragments with the same index belong to the same call; `arguments`
+/// fragments concatenate into the call's JSON arguments
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: String,
+}
+
+/// What a provider streams back for a chat request
+#[derive(Debug)]
+pub enum ChatEvent {
+    /// A piece of the answer
+    ContentDelta(String),
+    /// A piece of the model's reasoning
+    ReasoningDelta(String),
+    /// A piece of a tool call
+    ToolCallDelta(ToolCallDelta),
+    /// Token counters, and timings when the upstream reports them
+    Usage(Metrics),
+    /// The answer is complete; the last event of a successful stream
+    Done(Option<StopReason>),
+    /// The provider failed; the last event of a failed stream
+    Error(ProviderError),
+}
+
+/// Assembles streamed tool call fragments into complete calls
+#[derive(Default)]
+pub struct ToolCallAccumulator {
+    calls: Vec<ToolCallDelta>,
+}
+
+impl ToolCallAccumulator {
+    pub fn push(&mut self, delta: &ToolCallDelta) {
+        match self.calls.iter_mut().find(|c| c.index == delta.index) {
+            Some(call) => {
+                if call.id.is_none() {
+                    call.id = delta.id.clone();
+                }
+                // the name comes whole, some gateways repeat it on every fragment
+                if let Some(name) = &delta.name
+                    && !name.is_empty()
+                {
+                    call.name = Some(name.clone());
+                }
+                call.arguments.push_str(&delta.arguments);
+            }
+            None => self.calls.push(delta.clone()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// The calls so far, with their arguments parsed into JSON objects
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        self.calls
+            .iter()
+            .map(|call| {
+                let arguments = if call.arguments.trim().is_empty() {
+                    Value::Object(Default::default())
+                } else {
+                    serde_json::from_str(&call.arguments).unwrap_or_else(|e| {
+                        warn!(
+                            "tool call arguments are not valid JSON ({}), passing them as a string",
+                            e
+                        );
+                        Value::String(call.arguments.clone())
+                    })
+                };
+                ToolCall {
+                    id: call.id.clone(),
+                    function: ToolCallFunction {
+                        index: Some(call.index),
+                        name: call.name.clone().unwrap_or_default(),
+                        arguments,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Tool call arguments as a JSON object, for upstreams that reject them encoded. Arguments kept as
+/// a string (not valid JSON when streamed, or encoded by the client) are parsed, or left empty
+pub fn arguments_object(arguments: &Value) -> Value {
+    match arguments {
+        Value::String(arguments) => serde_json::from_str(arguments)
+            .ok()
+            .filter(Value::is_object)
+            .unwrap_or_else(|| Value::Object(Default::default())),
+        arguments => arguments.clone(),
+    }
+}
+
 // 定义可克隆的 Provider trait
 #[async_trait::async_trait]
 pub trait Provider {
-    fn chat(
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError>;
+
+    /// Completion for `/api/generate`, continuing the prior turns in `history`. By default the
+    /// request is translated into chat messages, providers with a native completion endpoint
+    /// override this
+    fn generate(
         &self,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
-    ) -> Result<ChatChunkStream, ProviderError>;
+        model: &str,
+        request: &GenerateRequest,
+        history: &[Message],
+    ) -> Result<ChatEventStream, ProviderError> {
+        self.chat(ChatCompletionRequest::from_generate(
+            model, request, history,
+        )?)
+    }
 
     async fn get_models(&self) -> Vec<Model>;
+
+    /// Whether `fetch_models` is implemented
+    fn lists_models(&self) -> bool {
+        false
+    }
+
+    /// The models the upstream itself lists, for model discovery
+    async fn fetch_models(&self) -> Result<Vec<Model>, ProviderError> {
+        Err(ProviderError::invalid_request(
+            "this provider cannot list its upstream's models".to_string(),
+        ))
+    }
+
+    /// Whether `tools` are passed on to the upstream, otherwise models only take them emulated
+    fn native_tools(&self) -> bool {
+        true
+    }
+
+    /// What `/api/show` reports about `model`, by default what the config declares. Providers
+    /// whose upstream can describe its models override this
+    async fn show(&self, model: &Model) -> Result<ShowResponse, ProviderError> {
+        Ok(ShowResponse::declared(model, self.native_tools()))
+    }
+}
+
+/// Gives tool calls ids and matches tool results to the call they answer, for upstreams that need
+/// ids when clients don't track them
+#[derive(Default)]
+pub struct ToolCallIds {
+    // (id, function name) of the calls not answered yet
+    pending: Vec<(String, String)>,
+}
+
+impl ToolCallIds {
+    /// The id of the `j`th call of message `i`, made up when the client sent none
+    pub fn call(&mut self, i: usize, j: usize, call: &ToolCall) -> String {
+        let id = call
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("call_{}_{}", i, j));
+        self.pending.push((id.clone(), call.function.name.clone()));
+        id
+    }
+
+    /// The id of the call a `tool` message answers: by id, by function name when the client sends
+    /// no id, or else the oldest unanswered call
+    pub fn result(&mut self, message: &Message) -> String {
+        let answered = match (&message.tool_call_id, &message.tool_name) {
+            (Some(id), _) => self.pending.iter().position(|(p, _)| p == id),
+            (None, Some(name)) => self.pending.iter().position(|(_, n)| n == name),
+            (None, None) => (!self.pending.is_empty()).then_some(0),
+        };
+        match answered {
+            Some(position) => self.pending.remove(position).0,
+            None => message.tool_call_id.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Guesses an image's MIME type from the magic bytes at the start of its base64 encoding
+pub fn sniff_image_mime(base64: &str) -> &'static str {
+    const SIGNATURES: [(&str, &str); 5] = [
+        ("iVBORw0KGgo", "image/png"),
+        ("/9j/", "image/jpeg"),
+        ("R0lGOD", "image/gif"),
+        ("UklGR", "image/webp"),
+        ("Qk", "image/bmp"),
+    ];
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| base64.starts_with(signature))
+        .map(|(_, mime)| *mime)
+        .unwrap_or("image/jpeg")
+}
+
+/// Rejects images sent to a model declared as text-only
+pub fn check_images(
+    models: &[Model],
+    model: &str,
+    messages: &[Message],
+) -> Result<(), ProviderError> {
+    let has_images = messages
+        .iter()
+        .any(|m| m.images.as_ref().is_some_and(|images| !images.is_empty()));
+    let text_only = models
+        .iter()
+        .any(|m| m.name == model && m.config.vision == Some(false));
+    if has_images && text_only {
+        return Err(ProviderError::invalid_request(format!(
+            "model '{}' does not support images",
+            model
+        )));
+    }
+    Ok(())
+}
+
+/// Translates a generate request into chat messages, rejecting what only makes sense against a
+/// raw completion endpoint
+pub fn generate_messages(
+    request: &GenerateRequest,
+    history: &[Message],
+) -> Result<Vec<Message>, ProviderError> {
+    if request.raw {
+        return Err(ProviderError::invalid_request(
+            "'raw' is not supported by this model's provider".to_string(),
+        ));
+    }
+    if request.template.is_some() {
+        return Err(ProviderError::invalid_request(
+            "'template' is not supported by this model's provider".to_string(),
+        ));
+    }
+    if request.suffix.is_some() {
+        return Err(ProviderError::invalid_request(
+            "'suffix' is not supported by this model's provider".to_string(),
+        ));
+    }
+
+    Ok(generate_conversation(request, history))
+}
+
+/// The conversation a generate request stands for: its system prompt, the prior turns and the
+/// prompt. A system prompt on the request replaces the one of the prior turns
+pub fn generate_conversation(request: &GenerateRequest, history: &[Message]) -> Vec<Message> {
+    let mut messages = Vec::new();
+    if let Some(system) = &request.system {
+        messages.push(Message::new("system", system.clone()));
+    }
+    messages.extend(
+        history
+            .iter()
+            .filter(|m| request.system.is_none() || m.role != "system")
+            .cloned(),
+    );
+    messages.push(Message {
+        images: request.images.clone(),
+        ..Message::new("user", request.prompt.clone())
+    });
+    messages
 }
 
 use futures::Stream;
 use std::pin::Pin;
-// 定义ChatChunkStream类型用于处理聊天流
+// 定义ChatEventStream类型用于处理聊天流
 
-pub type ChatChunkStream =
-    Pin<Box<dyn Stream<Item = Result<StreamChatChunk, ProviderError>> + Send>>;
+pub type ChatEventStream = Pin<Box<dyn Stream<Item = ChatEvent> + Send>>;
@@ -1,10 +1,15 @@
-use crate::models::{Message, Model, StreamChatChunk};
-use crate::providers::{ChatChunkStream, Provider, ProviderError};
-use chrono;
+use crate::models::{
+    GenerateRequest, Message, Metrics, Model, ModelDetails, ShowResponse, ToolCall,
+};
+use crate::providers::{
+    check_images, generate_conversation, http_client, response_lines, send, send_json,
+    ChatCompletionRequest, ChatEvent, ChatEventStream, Provider, ProviderError, ProviderErrorKind,
+    StopReason, ToolCallDelta,
+};
 use futures::StreamExt;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::time::Duration;
+use tracing::warn;
 #[derive(Clone)]
 pub struct OllamaProvider {
     base_url: String,
@@ -12,15 +17,45 @@ pub struct OllamaProvider {
     models: Vec<Model>,
 }
 
+// the reply of /api/tags
+#[derive(Deserialize)]
+struct TagsResponse {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+    modified_at: Option<String>,
+    size: Option<u64>,
+    digest: Option<String>,
+    details: Option<ModelDetails>,
+}
+
+// a line of either /api/chat (message) or /api/generate (response)
 #[derive(Deserialize)]
 struct OllamaChatChunk {
     message: Option<MessageContent>,
+    response: Option<String>,
+    // the reasoning, on /api/generate lines
+    thinking: Option<String>,
+    // absent from the error line Ollama ends a failed stream with
+    #[serde(default)]
     done: bool,
+    done_reason: Option<String>,
+    error: Option<String>,
+    // counters, set on the final line
+    #[serde(flatten)]
+    metrics: Metrics,
 }
 
 #[derive(Deserialize)]
 struct MessageContent {
+    #[serde(default)]
     content: String,
+    thinking: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
 }
 
 impl OllamaProvider {
@@ -32,39 +67,52 @@ impl OllamaProvider {
         }
     }
 
-    fn build_client(&self) -> Result<reqwest::Client, ProviderError> {
-        reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(10))
-            .timeout(Duration::from_secs(120))
-            .build()
-            .map_err(|e| ProviderError {
-                message: format!("Failed to build HTTP client: {}", e),
-                request_url: None,
-            })
+    fn build_request_body(&self, request: &ChatCompletionRequest) -> Value {
+        // Build base body
+        let mut body = json!({
+            "model": request.model,
+            "messages": request.messages,
+            "stream": true,
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools);
+        }
+        if let Some(format) = &request.format {
+            body["format"] = format.clone();
+        }
+        if let Some(think) = &request.think {
+            body["think"] = json!(think);
+        }
+        if let Some(options) = &request.options {
+            body["options"] = json!(options);
+        }
+        body
     }
-    fn build_request_body(
-        &self,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
-    ) -> Value {
-        let msgs: Vec<Value> = messages
-            .iter()
-            .map(|m| json!({ "role": m.role, "content": m.content }))
-            .collect();
 
-        // Build base body
+    fn build_generate_body(&self, model: &str, request: &GenerateRequest) -> Value {
         let mut body = json!({
             "model": model,
-            "messages": msgs,
+            "prompt": request.prompt,
+            "raw": request.raw,
             "stream": true,
         });
-
-        // Merge options if provided
-        if let Some(Value::Object(opts)) = option {
-            if let Some(obj) = body.as_object_mut() {
-                for (k, v) in opts {
-                    obj.insert(k, v);
+        if let Some(obj) = body.as_object_mut() {
+            let optional = [
+                ("suffix", request.suffix.clone().map(Value::from)),
+                ("system", request.system.clone().map(Value::from)),
+                ("template", request.template.clone().map(Value::from)),
+                ("images", request.images.clone().map(Value::from)),
+                ("format", request.format.clone()),
+                ("keep_alive", request.keep_alive.clone()),
+                ("think", request.think.as_ref().map(|think| json!(think))),
+                (
+                    "options",
+                    request.options.as_ref().map(|options| json!(options)),
+                ),
+            ];
+            for (k, v) in optional {
+                if let Some(v) = v {
+                    obj.insert(k.to_string(), v);
                 }
             }
         }
@@ -74,134 +122,175 @@ impl OllamaProvider {
     fn build_request(
         &self,
         url: &str,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
+        body: &Value,
     ) -> Result<reqwest::RequestBuilder, ProviderError> {
-        let client = self.build_client()?;
-        let body = self.build_request_body(model, messages, option);
+        let client = http_client()?;
 
         let request_builder = client
             .post(url)
             .header("Content-Type", "application/json")
             .header("Authorization", format!("Bearer {}", self.secret))
-            .json(&body);
+            .json(body);
 
         Ok(request_builder)
     }
-}
 
-#[async_trait::async_trait]
-impl Provider for OllamaProvider {
-    fn chat(
+    /// Sends a streaming request to an Ollama endpoint and reads its NDJSON reply
+    fn stream_request(
         &self,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
-    ) -> Result<ChatChunkStream, ProviderError> {
-        let model_name = model.clone();
-        let request_url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
-        let request = self.build_request(&request_url, model, messages, option)?;
+        request_url: String,
+        body: &Value,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let request = self.build_request(&request_url, body)?;
 
         let stream = async_stream::stream! {
 
-            let response = match request
-                .send()
-                .await
-            {
+            let response = match send(request, &request_url).await {
                 Ok(response) => response,
                 Err(e) => {
-                    yield Err(ProviderError {
-                        message: format!("HTTP request failed: {}", e),
-                        request_url: Some(request_url.clone()),
-                    });
+                    yield ChatEvent::Error(e);
                     return;
                 }
             };
+            let mut lines = Box::pin(response_lines(response, request_url.clone()));
+            let mut tool_call_count = 0;
 
-            if !response.status().is_success()  {
-                let status = response.status();
-                let error_text = response.text().await.unwrap_or_default();
-                yield Err(ProviderError {
-                    message: format!("HTTP error {}: {}", status, error_text),
-                    request_url: Some(request_url.clone()),
-                });
-                return;
-            }
-
-            let mut stream = response.bytes_stream();
-            let mut buffer = String::new();
-
-            while let Some(chunk_result) = stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(chunk) => chunk,
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
                     Err(e) => {
-                        yield Err(ProviderError {
-                            message: format!("Stream read error: {}", e),
-                            request_url: Some(request_url.clone()),
-                        });
+                        yield ChatEvent::Error(e);
                         return;
                     }
                 };
-
-                let chunk_str = match std::str::from_utf8(&chunk) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        yield Err(ProviderError {
-                            message: format!("UTF-8 decode error: {}", e),
+                match serde_json::from_str::<OllamaChatChunk>(&line) {
+                    // a failure after the response started, such as the model running out of memory
+                    Ok(OllamaChatChunk { error: Some(error), .. }) => {
+                        yield ChatEvent::Error(ProviderError {
+                            kind: ProviderErrorKind::Status(500),
+                            message: error,
                             request_url: Some(request_url.clone()),
                         });
                         return;
                     }
-                };
-
-                buffer.push_str(chunk_str);
-
-                // Process complete lines
-                while let Some(line_end) = buffer.find('\n') {
-                    let line = buffer[..line_end].trim().to_string();
-                    buffer.drain(..=line_end);
-
-                    if line.is_empty() {
-                        continue;
-                    }
-
-                    match serde_json::from_str::<OllamaChatChunk>(&line) {
-                        Ok(chunk) => {
-                            if let Some(message) = chunk.message {
-                                let thunk = StreamChatChunk {
-                                    model: model_name.clone(),
-                                    created_at: chrono::Utc::now().to_rfc3339(),
-                                    message: Message {
-                                        role: "assistant".to_string(),
-                                        content: message.content.clone(),
-                                    },
-                                    done: chunk.done,
-                                };
-
-                                yield Ok(thunk);
-
-                                if chunk.done {
-                                    return;
-                                }
-                            }
+                    Ok(chunk) => {
+                        let (content, thinking, tool_calls) = match (chunk.message, chunk.response) {
+                            (Some(message), _) => (message.content, message.thinking, message.tool_calls),
+                            (None, Some(response)) => (response, chunk.thinking, Vec::new()),
+                            (None, None) => (String::new(), chunk.thinking, Vec::new()),
+                        };
+                        if let Some(thinking) = thinking
+                            && !thinking.is_empty()
+                        {
+                            yield ChatEvent::ReasoningDelta(thinking);
+                        }
+                        if !content.is_empty() {
+                            yield ChatEvent::ContentDelta(content);
                         }
-                        Err(e) => {
-                            yield Err(ProviderError {
-                                message: format!("JSON parse error: {}", e),
-                                request_url: Some(request_url.clone()),
+                        // Ollama sends each call whole
+                        for call in tool_calls {
+                            yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                index: tool_call_count,
+                                id: call.id,
+                                name: Some(call.function.name),
+                                arguments: call.function.arguments.to_string(),
                             });
+                            tool_call_count += 1;
+                        }
+
+                        if chunk.done {
+                            yield ChatEvent::Usage(chunk.metrics);
+                            yield ChatEvent::Done(chunk.done_reason.as_deref().map(StopReason::from_upstream));
                             return;
                         }
                     }
+                    Err(e) => {
+                        yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                        return;
+                    }
                 }
             }
+
+            // the stream ended without a done line
+            warn!("{} closed the stream before it was done", request_url);
+            yield ChatEvent::Done(None);
         };
 
         Ok(Box::pin(stream))
     }
+}
+
+#[async_trait::async_trait]
+impl Provider for OllamaProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_images(&self.models, &request.model, &request.messages)?;
+        let request_url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        let body = self.build_request_body(&request);
+        self.stream_request(request_url, &body)
+    }
+
+    // Ollama understands every generate field natively, so skip the chat translation unless
+    // prior turns have to be replayed. A raw prompt, a template or a suffix only make sense
+    // against /api/generate, which cannot take the turns, so these are sent without them
+    fn generate(
+        &self,
+        model: &str,
+        request: &GenerateRequest,
+        history: &[Message],
+    ) -> Result<ChatEventStream, ProviderError> {
+        let native_only = request.raw || request.template.is_some() || request.suffix.is_some();
+        if !history.is_empty() {
+            if !native_only {
+                return self.chat(ChatCompletionRequest::from_generate(
+                    model, request, history,
+                )?);
+            }
+            warn!(
+                "the prior turns of 'context' cannot be replayed with 'raw', 'template' or \
+                 'suffix', sending the prompt alone"
+            );
+        }
+        check_images(&self.models, model, &generate_conversation(request, &[]))?;
+        let request_url = format!("{}/api/generate", self.base_url.trim_end_matches('/'));
+        let body = self.build_generate_body(model, request);
+        self.stream_request(request_url, &body)
+    }
 
     async fn get_models(&self) -> Vec<Model> {
         self.models.clone()
     }
+
+    fn lists_models(&self) -> bool {
+        true
+    }
+
+    async fn fetch_models(&self) -> Result<Vec<Model>, ProviderError> {
+        let request_url = format!("{}/api/tags", self.base_url.trim_end_matches('/'));
+        let request = http_client()?
+            .get(&request_url)
+            .header("Authorization", format!("Bearer {}", self.secret));
+        let tags: TagsResponse = send_json(request, &request_url).await?;
+        Ok(tags
+            .models
+            .into_iter()
+            .map(|tag| Model {
+                modified_at: tag.modified_at,
+                size: tag.size,
+                digest: tag.digest,
+                details: tag.details,
+                ..Model::named(tag.name)
+            })
+            .collect())
+    }
+
+    async fn show(&self, model: &Model) -> Result<ShowResponse, ProviderError> {
+        let request_url = format!("{}/api/show", self.base_url.trim_end_matches('/'));
+        let request = http_client()?
+            .post(&request_url)
+            .header("Authorization", format!("Bearer {}", self.secret))
+            .json(&serde_json::json!({ "model": model.name }));
+        let mut show: ShowResponse = send_json(request, &request_url).await?;
+        show.apply_config(&model.config);
+        Ok(show)
+    }
 }
@@ -1,30 +1,165 @@
-use crate::models::{Message, Model, StreamChatChunk};
-use crate::providers::{ChatChunkStream, Provider, ProviderError};
-use chrono;
-use futures::StreamExt;
+use crate::models::{FimMode, GenerateRequest, Message, Metrics, Model, ModelOptions, Think};
+use crate::providers::{
+    check_images, http_client, response_lines, send, send_json, sniff_image_mime,
+    ChatCompletionRequest, ChatEvent, ChatEventStream, Provider, ProviderError, ProviderErrorKind,
+    StopReason, ToolCallDelta, ToolCallIds,
+};
+use futures::{Stream, StreamExt};
 use serde::Deserialize;
-use serde_json::{json, Value};
-use std::time::Duration;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use tracing::warn;
+
+// the latest GA api-version of Azure OpenAI, used when the provider sets none
+const AZURE_API_VERSION: &str = "2024-10-21";
+
 #[derive(Clone)]
 pub struct OpenAIProvider {
     key: String,
     models: Vec<Model>,
     base_url: String,
+    flavor: Flavor,
+}
+
+// how requests are addressed and authenticated
+#[derive(Clone)]
+enum Flavor {
+    // `{base_url}/chat/completions` with a bearer token
+    OpenAI,
+    // `{base_url}/openai/deployments/{deployment}/chat/completions?api-version=...` with an
+    // api-key header
+    Azure { api_version: String },
+}
+
+// the reply of /models
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelId>,
+}
+
+#[derive(Deserialize)]
+struct ModelId {
+    id: String,
 }
 
 #[derive(Deserialize)]
 struct OpenaiChatChunk {
+    #[serde(default)]
     choices: Vec<Choice>,
+    // only on the last chunk, when requested with stream_options.include_usage
+    usage: Option<Usage>,
+    // Azure's content filter verdicts on the prompt, on the first chunk
+    #[serde(default)]
+    prompt_filter_results: Vec<PromptFilterResult>,
+    // how gateways report a failure after the answer started
+    error: Option<ErrorBody>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+    // an HTTP status, or a name such as rate_limit_exceeded
+    code: Option<Value>,
+}
+
+impl ErrorBody {
+    fn into_provider_error(self, request_url: &str) -> ProviderError {
+        let status = self
+            .code
+            .as_ref()
+            .and_then(Value::as_u64)
+            .filter(|code| (400..600).contains(code))
+            .unwrap_or(500);
+        let message = match self.code {
+            Some(Value::String(code)) => format!("{}: {}", code, self.message),
+            _ => self.message,
+        };
+        ProviderError {
+            kind: ProviderErrorKind::Status(status as u16),
+            message,
+            request_url: Some(request_url.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PromptFilterResult {
+    #[serde(default)]
+    content_filter_results: Map<String, Value>,
 }
 
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+// a choice of either /chat/completions (delta) or /completions (text)
 #[derive(Deserialize)]
 struct Choice {
     delta: Option<Delta>,
+    text: Option<String>,
+    finish_reason: Option<String>,
+    // Azure's content filter verdicts on the answer so far
+    content_filter_results: Option<Map<String, Value>>,
 }
 
 #[derive(Deserialize)]
 struct Delta {
     content: Option<String>,
+    // reasoning, named reasoning_content by DeepSeek-style gateways and reasoning by others;
+    // some send both
+    reasoning_content: Option<String>,
+    reasoning: Option<String>,
+    tool_calls: Option<Vec<DeltaToolCall>>,
+}
+
+// a fragment of a streamed tool call
+#[derive(Deserialize)]
+struct DeltaToolCall {
+    // some gateways leave it out and send each call whole
+    index: Option<usize>,
+    id: Option<String>,
+    function: Option<DeltaFunction>,
+}
+
+/// Numbers the calls of a stream. Fragments are matched to their call by `id`, then by upstream
+/// `index`, so gateways that send each call whole, without an index or all at index 0, don't merge
+/// parallel calls
+#[derive(Default)]
+struct ToolCallIndexes {
+    by_id: HashMap<String, usize>,
+    by_index: HashMap<usize, usize>,
+    count: usize,
+}
+
+impl ToolCallIndexes {
+    fn resolve(&mut self, id: Option<&str>, index: Option<usize>) -> usize {
+        let id = id.filter(|id| !id.is_empty());
+        let known = match (id, index) {
+            (Some(id), _) => self.by_id.get(id).copied(),
+            (None, Some(index)) => self.by_index.get(&index).copied(),
+            // a continuation of the last call
+            (None, None) => self.count.checked_sub(1),
+        };
+        let resolved = known.unwrap_or_else(|| {
+            self.count += 1;
+            self.count - 1
+        });
+        if let Some(id) = id {
+            self.by_id.insert(id.to_string(), resolved);
+        }
+        if let Some(index) = index {
+            self.by_index.insert(index, resolved);
+        }
+        resolved
+    }
+}
+
+#[derive(Deserialize)]
+struct DeltaFunction {
+    name: Option<String>,
+    arguments: Option<String>,
 }
 
 impl OpenAIProvider {
@@ -33,46 +168,190 @@ impl OpenAIProvider {
             key,
             base_url,
             models,
+            flavor: Flavor::OpenAI,
         }
     }
 
-    fn build_client(&self) -> Result<reqwest::Client, ProviderError> {
-        reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(10))
-            .timeout(Duration::from_secs(120))
-            .build()
-            .map_err(|e| ProviderError {
-                message: format!("Failed to build HTTP client: {}", e),
-                request_url: None,
-            })
+    /// An Azure OpenAI resource, `base_url` being its endpoint
+    /// (`https://{resource}.openai.azure.com`)
+    pub fn azure(
+        base_url: String,
+        key: String,
+        api_version: Option<String>,
+        models: Vec<Model>,
+    ) -> Self {
+        Self {
+            key,
+            base_url,
+            models,
+            flavor: Flavor::Azure {
+                api_version: api_version.unwrap_or_else(|| AZURE_API_VERSION.to_string()),
+            },
+        }
     }
 
-    fn build_request_body(
-        &self,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
-    ) -> Value {
-        let msgs: Vec<Value> = messages
-            .iter()
-            .map(|m| json!({ "role": m.role, "content": m.content }))
-            .collect();
+    /// The url of an endpoint (`chat/completions` or `completions`) for a model
+    fn endpoint_url(&self, model: &str, endpoint: &str) -> String {
+        let base_url = self.base_url.trim_end_matches('/');
+        match &self.flavor {
+            Flavor::OpenAI => format!("{}/{}", base_url, endpoint),
+            Flavor::Azure { api_version } => {
+                let deployment = self
+                    .models
+                    .iter()
+                    .find(|m| m.name == model)
+                    .and_then(|m| m.config.deployment.as_deref())
+                    .unwrap_or(model);
+                format!(
+                    "{}/openai/deployments/{}/{}?api-version={}",
+                    base_url, deployment, endpoint, api_version
+                )
+            }
+        }
+    }
+
+    /// Translates Ollama options into OpenAI request parameters, logging the ones that have no
+    /// equivalent
+    fn apply_options(body: &mut Value, options: Option<&ModelOptions>) {
+        let Some(options) = options else {
+            return;
+        };
+        // Ollama reads a negative num_predict as "no limit"
+        if let Some(num_predict) = options.num_predict
+            && num_predict >= 0
+        {
+            body["max_tokens"] = json!(num_predict);
+        }
+        let mapped = [
+            ("stop", options.stop.as_ref().map(|v| json!(v))),
+            ("seed", options.seed.map(|v| json!(v))),
+            ("top_p", options.top_p.map(|v| json!(v))),
+            ("temperature", options.temperature.map(|v| json!(v))),
+            (
+                "presence_penalty",
+                options.presence_penalty.map(|v| json!(v)),
+            ),
+            (
+                "frequency_penalty",
+                options.frequency_penalty.map(|v| json!(v)),
+            ),
+        ];
+        for (k, v) in mapped {
+            if let Some(v) = v {
+                body[k] = v;
+            }
+        }
+        for key in options.extra.keys() {
+            warn!("option '{}' has no OpenAI equivalent, ignoring it", key);
+        }
+    }
+
+    /// Message content: plain text, or content parts when the message carries images
+    fn build_content(message: &Message) -> Value {
+        let images = match &message.images {
+            Some(images) if !images.is_empty() => images,
+            _ => return json!(message.content),
+        };
+        let mut parts = vec![json!({ "type": "text", "text": message.content })];
+        for image in images {
+            let url = if image.starts_with("data:") || image.starts_with("http") {
+                image.clone()
+            } else {
+                format!("data:{};base64,{}", sniff_image_mime(image), image)
+            };
+            parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+        }
+        json!(parts)
+    }
+
+    /// Translates Ollama-style messages. Tool calls get ids and string-encoded arguments, and tool
+    /// results are matched to the call they answer, by name when the client sends no id
+    fn build_messages(messages: &[Message]) -> Vec<Value> {
+        let mut ids = ToolCallIds::default();
+        let mut msgs = Vec::with_capacity(messages.len());
+        for (i, m) in messages.iter().enumerate() {
+            let msg = match (m.role.as_str(), &m.tool_calls) {
+                ("assistant", Some(tool_calls)) => {
+                    let calls: Vec<Value> = tool_calls
+                        .iter()
+                        .enumerate()
+                        .map(|(j, call)| {
+                            let arguments = match &call.function.arguments {
+                                Value::String(arguments) => arguments.clone(),
+                                arguments => arguments.to_string(),
+                            };
+                            json!({
+                                "id": ids.call(i, j, call),
+                                "type": "function",
+                                "function": { "name": call.function.name, "arguments": arguments },
+                            })
+                        })
+                        .collect();
+                    let content = if m.content.is_empty() {
+                        Value::Null
+                    } else {
+                        json!(m.content)
+                    };
+                    json!({ "role": "assistant", "content": content, "tool_calls": calls })
+                }
+                ("tool", _) => {
+                    json!({ "role": "tool", "tool_call_id": ids.result(m), "content": m.content })
+                }
+                _ => json!({ "role": m.role, "content": Self::build_content(m) }),
+            };
+            msgs.push(msg);
+        }
+        msgs
+    }
 
+    fn build_request_body(&self, request: &ChatCompletionRequest) -> Value {
         // Build base body
         let mut body = json!({
-            "model": model,
-            "messages": msgs,
+            "model": request.model,
+            "messages": Self::build_messages(&request.messages),
             "stream": true,
+            "stream_options": { "include_usage": true },
         });
-
-        // Merge options if provided
-        if let Some(Value::Object(opts)) = option {
-            if let Some(obj) = body.as_object_mut() {
-                for (k, v) in opts {
-                    obj.insert(k, v);
-                }
-            }
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools);
         }
+        if let Some(format) = &request.format
+            && let Some(response_format) = response_format(format)
+        {
+            body["response_format"] = response_format;
+        }
+        // there is no portable way to turn reasoning off, `think: false` leaves the upstream default
+        match &request.think {
+            Some(Think::Effort(effort)) => body["reasoning_effort"] = json!(effort),
+            Some(Think::Enabled(true)) => body["reasoning_effort"] = json!("medium"),
+            Some(Think::Enabled(false)) | None => {}
+        }
+
+        Self::apply_options(&mut body, request.options.as_ref());
+
+        body
+    }
+
+    /// Builds a legacy `/completions` body for a fill-in-the-middle request
+    fn build_fim_body(&self, model: &str, fim: &FimMode, request: &GenerateRequest) -> Value {
+        let suffix = request.suffix.clone().unwrap_or_default();
+        let mut body = match fim {
+            FimMode::Completions => json!({
+                "model": model,
+                "prompt": request.prompt,
+                "suffix": suffix,
+                "stream": true,
+                "stream_options": { "include_usage": true },
+            }),
+            FimMode::Template { template } => json!({
+                "model": model,
+                "prompt": fill_fim_template(template, &request.prompt, &suffix),
+                "stream": true,
+                "stream_options": { "include_usage": true },
+            }),
+        };
+
+        Self::apply_options(&mut body, request.options.as_ref());
 
         body
     }
@@ -80,159 +359,384 @@ impl OpenAIProvider {
     fn build_request(
         &self,
         url: &str,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
+        body: &Value,
     ) -> Result<reqwest::RequestBuilder, ProviderError> {
-        let client = self.build_client()?;
-        let body = self.build_request_body(model, messages, option);
-        let key = self.key.clone();
+        let client = http_client()?;
 
-        let builder = client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", key))
+        let builder = match self.flavor {
+            Flavor::OpenAI => client
+                .post(url)
+                .header("Authorization", format!("Bearer {}", self.key)),
+            Flavor::Azure { .. } => client.post(url).header("api-key", &self.key),
+        };
+        let builder = builder
             .header("Content-Type", "application/json")
-            .json(&body);
+            .json(body);
 
         Ok(builder)
     }
-}
 
-#[async_trait::async_trait]
-impl Provider for OpenAIProvider {
-    fn chat(
+    /// Sends a streaming request to an OpenAI endpoint and reads its server-sent events
+    fn stream_request(
         &self,
-        model: &String,
-        messages: &[Message],
-        option: Option<Value>,
-    ) -> Result<ChatChunkStream, ProviderError> {
-        let model_name = model.clone();
-        let request_url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
-        let request = self.build_request(&request_url, model, messages, option)?;
+        request_url: String,
+        body: &Value,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let request = self.build_request(&request_url, body)?;
 
         let stream = async_stream::stream! {
-            let response = match request
-                .send()
-                .await
-            {
+            let response = match send(request, &request_url).await {
                 Ok(response) => response,
                 Err(e) => {
-                    yield Err(ProviderError {
-                        message: format!("HTTP request failed: {}", e),
-                        request_url: Some(request_url.clone()),
-                    });
+                    yield ChatEvent::Error(e);
                     return;
                 }
             };
-
-            if !response.status().is_success() {
-                let status = response.status();
-                let error_text = response.text().await.unwrap_or_default();
-                yield Err(ProviderError {
-                    message: format!("HTTP error {}: {}", status, error_text),
-                    request_url: Some(request_url.clone()),
-                });
-                return;
+            let mut events = Box::pin(chat_events(response, request_url));
+            while let Some(event) = events.next().await {
+                yield event;
             }
+        };
 
+        Ok(Box::pin(stream))
+    }
+}
 
-            let mut stream = response.bytes_stream();
-            let mut buffer = String::new();
-            let mut stream_ended = false;
+/// Reads the server-sent events of a /chat/completions or /completions answer
+fn chat_events(
+    response: reqwest::Response,
+    request_url: String,
+) -> impl Stream<Item = ChatEvent> + Send {
+    async_stream::stream! {
+        let mut lines = Box::pin(response_lines(response, request_url.clone()));
+        let mut done_reason = None;
+        let mut filtered = false;
+        let mut tool_call_indexes = ToolCallIndexes::default();
 
-            while let Some(chunk_result) = stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(chunk) => chunk,
-                    Err(e) => {
-                        yield Err(ProviderError {
-                            message: format!("Stream read error: {}", e),
-                            request_url: Some(request_url.clone()),
-                        });
-                        return;
-                    }
-                };
+        while let Some(line) = lines.next().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+            if line == "data: [DONE]" {
+                break;
+            }
 
-                let chunk_str = match std::str::from_utf8(&chunk) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        yield Err(ProviderError {
-                            message: format!("UTF-8 decode error: {}", e),
-                            request_url: Some(request_url.clone()),
-                        });
+            if let Some(data) = line.strip_prefix("data: ") {
+                match serde_json::from_str::<OpenaiChatChunk>(data) {
+                    Ok(OpenaiChatChunk { error: Some(error), .. }) => {
+                        yield ChatEvent::Error(error.into_provider_error(&request_url));
                         return;
                     }
-                };
-
-                buffer.push_str(chunk_str);
-
-                // Process complete lines
-                while let Some(line_end) = buffer.find('\n') {
-                    let line = buffer[..line_end].trim().to_string();
-                    buffer.drain(..=line_end);
-
-                    if line.is_empty() {
-                        continue;
-                    }
-
-                    if line == "data: [DONE]" {
-                        stream_ended = true;
-                        break;
-                    }
-
-                    if let Some(data) = line.strip_prefix("data: ") {
-                        match serde_json::from_str::<OpenaiChatChunk>(data) {
-                            Ok(chunk) => {
-                                if let Some(choice) = chunk.choices.first() {
-                                    if let Some(delta) = &choice.delta {
-                                        if let Some(content) = &delta.content {
-                                            let thunk = StreamChatChunk {
-                                                model: model_name.clone(),
-                                                created_at: chrono::Utc::now().to_rfc3339(),
-                                                message: Message {
-                                                    role: "assistant".to_string(),
-                                                    content: content.clone(),
-                                                },
-                                                done: false,
-                                            };
-
-                                            yield Ok(thunk);
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                yield Err(ProviderError {
-                                    message: format!("JSON parse error: {}", e),
+                    Ok(chunk) => {
+                        if let Some(usage) = &chunk.usage {
+                            yield ChatEvent::Usage(Metrics {
+                                prompt_eval_count: usage.prompt_tokens,
+                                eval_count: usage.completion_tokens,
+                                ..Default::default()
+                            });
+                        }
+                        match chunk.choices.first().and_then(|c| c.finish_reason.as_deref()) {
+                            // some gateways end a failed answer this way, without an error object
+                            Some("error") => {
+                                yield ChatEvent::Error(ProviderError {
+                                    kind: ProviderErrorKind::Status(500),
+                                    message: "the upstream ended the answer with an error".to_string(),
                                     request_url: Some(request_url.clone()),
                                 });
                                 return;
                             }
+                            Some(reason) => done_reason = Some(StopReason::from_upstream(reason)),
+                            None => {}
+                        }
+                        let filter_results = chunk
+                            .prompt_filter_results
+                            .iter()
+                            .map(|r| &r.content_filter_results)
+                            .chain(chunk.choices.first().and_then(|c| c.content_filter_results.as_ref()));
+                        for results in filter_results {
+                            let categories = filtered_categories(results);
+                            if !categories.is_empty() {
+                                warn!("content filter triggered: {}", categories.join(", "));
+                                filtered = true;
+                            }
+                        }
+                        let Some(choice) = chunk.choices.into_iter().next() else {
+                            continue;
+                        };
+                        let (content, reasoning, tool_calls) = match choice.delta {
+                            Some(delta) => (delta.content, delta.reasoning_content.or(delta.reasoning), delta.tool_calls.unwrap_or_default()),
+                            None => (choice.text, None, Vec::new()),
+                        };
+                        if let Some(reasoning) = reasoning
+                            && !reasoning.is_empty()
+                        {
+                            yield ChatEvent::ReasoningDelta(reasoning);
+                        }
+                        if let Some(content) = content
+                            && !content.is_empty()
+                        {
+                            yield ChatEvent::ContentDelta(content);
+                        }
+                        for call in tool_calls {
+                            let function = call.function;
+                            yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                index: tool_call_indexes.resolve(call.id.as_deref(), call.index),
+                                id: call.id,
+                                name: function.as_ref().and_then(|f| f.name.clone()),
+                                arguments: function.and_then(|f| f.arguments).unwrap_or_default(),
+                            });
                         }
                     }
-                }
-
-                if stream_ended {
-                    break;
+                    Err(e) => {
+                        yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                        return;
+                    }
                 }
             }
+        }
 
-            // Send a final "done" message
-            let final_chunk = StreamChatChunk {
-                model: model_name,
-                created_at: chrono::Utc::now().to_rfc3339(),
-                message: Message {
-                    role: "assistant".to_string(),
-                    content: "".to_string(),
-                },
-                done: true,
-            };
-            yield Ok(final_chunk);
+        if filtered {
+            done_reason = Some(StopReason::ContentFilter);
+        }
+        // Send a final "done" message
+        yield ChatEvent::Done(done_reason);
+    }
+}
+
+/// Puts the prompt and suffix in place of `{prefix}` and `{suffix}`, in one pass so that
+/// placeholders in the code itself (a `format!("{suffix}")`, an f-string) are left alone
+fn fill_fim_template(template: &str, prefix: &str, suffix: &str) -> String {
+    let mut filled = String::with_capacity(template.len() + prefix.len() + suffix.len());
+    let mut rest = template;
+    loop {
+        let next = [("{prefix}", prefix), ("{suffix}", suffix)]
+            .into_iter()
+            .filter_map(|(placeholder, value)| {
+                rest.find(placeholder).map(|at| (at, placeholder, value))
+            })
+            .min_by_key(|(at, ..)| *at);
+        let Some((at, placeholder, value)) = next else {
+            break;
         };
+        filled.push_str(&rest[..at]);
+        filled.push_str(value);
+        rest = &rest[at + placeholder.len()..];
+    }
+    filled.push_str(rest);
+    filled
+}
 
-        Ok(Box::pin(stream))
+/// The categories (hate, violence, jailbreak, ...) that Azure's content filter flagged as
+/// `filtered`
+fn filtered_categories(results: &Map<String, Value>) -> Vec<&str> {
+    results
+        .iter()
+        .filter(|(_, verdict)| verdict["filtered"] == true)
+        .map(|(category, _)| category.as_str())
+        .collect()
+}
+
+/// Translates Ollama's `format`, "json" or a JSON schema, into a `response_format`
+fn response_format(format: &Value) -> Option<Value> {
+    match format {
+        Value::String(format) if format == "json" => Some(json!({ "type": "json_object" })),
+        Value::Object(_) => Some(json!({
+            "type": "json_schema",
+            "json_schema": { "name": "response", "schema": format, "strict": true },
+        })),
+        other => {
+            warn!("unsupported format {}, ignoring it", other);
+            None
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for OpenAIProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_images(&self.models, &request.model, &request.messages)?;
+        let request_url = self.endpoint_url(&request.model, "chat/completions");
+        let body = self.build_request_body(&request);
+        self.stream_request(request_url, &body)
+    }
+
+    // fill-in-the-middle goes through the legacy completions endpoint, everything else is chat
+    fn generate(
+        &self,
+        model: &str,
+        request: &GenerateRequest,
+        history: &[Message],
+    ) -> Result<ChatEventStream, ProviderError> {
+        if request.suffix.is_none() {
+            return self.chat(ChatCompletionRequest::from_generate(
+                model, request, history,
+            )?);
+        }
+
+        let fim = self
+            .models
+            .iter()
+            .find(|m| m.name == model)
+            .and_then(|m| m.config.fim.as_ref())
+            .ok_or_else(|| {
+                ProviderError::invalid_request(format!(
+                    "'suffix' needs a fim mode configured for model '{}'",
+                    model
+                ))
+            })?;
+        if request.raw || request.template.is_some() || request.images.is_some() {
+            return Err(ProviderError::invalid_request(
+                "'suffix' cannot be combined with raw, template or images".to_string(),
+            ));
+        }
+
+        if request.format.is_some() {
+            warn!("'format' is not supported together with 'suffix', ignoring it");
+        }
+
+        let request_url = self.endpoint_url(model, "completions");
+        let body = self.build_fim_body(model, fim, request);
+        self.stream_request(request_url, &body)
     }
 
     async fn get_models(&self) -> Vec<Model> {
         self.models.clone()
     }
+
+    // Azure lists its deployments through the management API only
+    fn lists_models(&self) -> bool {
+        matches!(self.flavor, Flavor::OpenAI)
+    }
+
+    async fn fetch_models(&self) -> Result<Vec<Model>, ProviderError> {
+        if !self.lists_models() {
+            return Err(ProviderError::invalid_request(
+                "Azure deployments cannot be listed with the resource's key".to_string(),
+            ));
+        }
+        let request_url = format!("{}/models", self.base_url.trim_end_matches('/'));
+        let request = http_client()?
+            .get(&request_url)
+            .header("Authorization", format!("Bearer {}", self.key));
+        let list: ModelList = send_json(request, &request_url).await?;
+        Ok(list.data.into_iter().map(|m| Model::named(m.id)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The events of an answer whose server-sent events are `lines`
+    async fn events(lines: &[&str]) -> Vec<ChatEvent> {
+        let body: String = lines.iter().map(|line| format!("{}\n\n", line)).collect();
+        let response = reqwest::Response::from(axum::http::Response::new(body));
+        chat_events(response, "http://upstream/v1/chat/completions".to_string())
+            .collect()
+            .await
+    }
+
+    #[test]
+    fn fim_template_leaves_placeholders_in_the_code_alone() {
+        let template = "<|fim_prefix|>{prefix}<|fim_suffix|>{suffix}<|fim_middle|>";
+        assert_eq!(
+            fill_fim_template(template, "let s = format!(\"{suffix}\");\n", "f\"{prefix}\""),
+            "<|fim_prefix|>let s = format!(\"{suffix}\");\n<|fim_suffix|>f\"{prefix}\"<|fim_middle|>"
+        );
+        assert_eq!(
+            fill_fim_template("{suffix}|{prefix}|{suffix}", "p", "s"),
+            "s|p|s"
+        );
+    }
+
+    #[tokio::test]
+    async fn error_line_ends_the_answer() {
+        let events = events(&[
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hel"}}]}"#,
+            r#"data: {"error":{"message":"upstream overloaded","code":503}}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":"lo"}}]}"#,
+        ])
+        .await;
+        match events.as_slice() {
+            [ChatEvent::ContentDelta(content), ChatEvent::Error(e)] => {
+                assert_eq!(content, "Hel");
+                assert_eq!(e.kind, ProviderErrorKind::Status(503));
+                assert_eq!(e.message, "upstream overloaded");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn error_line_with_a_named_code() {
+        let events = events(&[
+            r#"data: {"error":{"message":"slow down","type":"requests","code":"rate_limit_exceeded"}}"#,
+        ])
+        .await;
+        match events.as_slice() {
+            [ChatEvent::Error(e)] => {
+                assert_eq!(e.kind, ProviderErrorKind::Status(500));
+                assert_eq!(e.message, "rate_limit_exceeded: slow down");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn error_finish_reason_ends_the_answer() {
+        let events = events(&[
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hel"}}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"error"}]}"#,
+            "data: [DONE]",
+        ])
+        .await;
+        assert!(matches!(events.last(), Some(ChatEvent::Error(_))));
+        assert!(!events.iter().any(|e| matches!(e, ChatEvent::Done(_))));
+    }
+
+    #[tokio::test]
+    async fn answer_ends_with_its_finish_reason() {
+        let events = events(&[
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":"stop"}]}"#,
+            "data: [DONE]",
+        ])
+        .await;
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ChatEvent::ContentDelta(_),
+                ChatEvent::Done(Some(StopReason::Stop))
+            ]
+        ));
+    }
+
+    #[test]
+    fn fragments_follow_their_call() {
+        let mut indexes = ToolCallIndexes::default();
+        assert_eq!(indexes.resolve(Some("a"), Some(0)), 0);
+        assert_eq!(indexes.resolve(None, Some(0)), 0);
+        assert_eq!(indexes.resolve(Some("b"), Some(1)), 1);
+        assert_eq!(indexes.resolve(None, Some(1)), 1);
+        assert_eq!(indexes.resolve(Some(""), Some(0)), 0);
+    }
+
+    #[test]
+    fn whole_calls_without_index_are_kept_apart() {
+        let mut indexes = ToolCallIndexes::default();
+        assert_eq!(indexes.resolve(Some("a"), None), 0);
+        assert_eq!(indexes.resolve(None, None), 0);
+        assert_eq!(indexes.resolve(Some("b"), None), 1);
+    }
+
+    #[test]
+    fn whole_calls_all_at_index_zero_are_kept_apart() {
+        let mut indexes = ToolCallIndexes::default();
+        assert_eq!(indexes.resolve(Some("a"), Some(0)), 0);
+        assert_eq!(indexes.resolve(Some("b"), Some(0)), 1);
+        assert_eq!(indexes.resolve(None, Some(0)), 1);
+    }
 }
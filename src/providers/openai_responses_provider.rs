@@ -0,0 +1,409 @@
+use crate::models::{Message, Metrics, Model, ModelOptions, Think};
+use crate::providers::{
+    check_images, http_client, response_lines, send, send_json, sniff_image_mime,
+    ChatCompletionRequest, ChatEvent, ChatEventStream, Provider, ProviderError, ProviderErrorKind,
+    StopReason, ToolCallDelta, ToolCallIds,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct OpenAIResponsesProvider {
+    key: String,
+    models: Vec<Model>,
+    base_url: String,
+}
+
+// the reply of /models
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelId>,
+}
+
+#[derive(Deserialize)]
+struct ModelId {
+    id: String,
+}
+
+// the server-sent events of /responses used here, by their `type`
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum StreamEvent {
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+    // a refusal replaces the answer
+    #[serde(rename = "response.refusal.delta")]
+    RefusalDelta { delta: String },
+    #[serde(
+        rename = "response.reasoning_summary_text.delta",
+        alias = "response.reasoning_text.delta"
+    )]
+    ReasoningDelta { delta: String },
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded { output_index: usize, item: Value },
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta { output_index: usize, delta: String },
+    #[serde(rename = "response.completed", alias = "response.incomplete")]
+    Finished { response: Response },
+    #[serde(rename = "response.failed")]
+    Failed { response: Response },
+    #[serde(rename = "error")]
+    Error {
+        code: Option<String>,
+        message: String,
+    },
+    // created, in_progress, content_part.added, *.done, ...
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    usage: Option<Usage>,
+    incomplete_details: Option<IncompleteDetails>,
+    error: Option<ResponseError>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct IncompleteDetails {
+    // max_output_tokens or content_filter
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct ResponseError {
+    code: String,
+    message: String,
+}
+
+impl OpenAIResponsesProvider {
+    pub fn new(base_url: String, key: String, models: Vec<Model>) -> Self {
+        Self {
+            key,
+            base_url,
+            models,
+        }
+    }
+
+    /// Message content: plain text, or input parts when the message carries images
+    fn build_content(message: &Message) -> Value {
+        let images = match &message.images {
+            Some(images) if !images.is_empty() => images,
+            _ => return json!(message.content),
+        };
+        let mut parts: Vec<Value> = images
+            .iter()
+            .map(|image| {
+                let url = if image.starts_with("http") {
+                    image.clone()
+                } else {
+                    format!("data:{};base64,{}", sniff_image_mime(image), image)
+                };
+                json!({ "type": "input_image", "image_url": url })
+            })
+            .collect();
+        parts.push(json!({ "type": "input_text", "text": message.content }));
+        json!(parts)
+    }
+
+    /// Translates Ollama-style messages into `input` items. System messages become the
+    /// `instructions`, tool calls `function_call` items and tool results `function_call_output`
+    /// items
+    fn build_input(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+        let mut instructions = Vec::new();
+        let mut ids = ToolCallIds::default();
+        let mut items: Vec<Value> = Vec::with_capacity(messages.len());
+        for (i, m) in messages.iter().enumerate() {
+            match m.role.as_str() {
+                "system" => instructions.push(m.content.clone()),
+                "tool" => items.push(json!({
+                    "type": "function_call_output",
+                    "call_id": ids.result(m),
+                    "output": m.content,
+                })),
+                role => {
+                    if !m.content.is_empty() || m.images.is_some() {
+                        items.push(json!({ "role": role, "content": Self::build_content(m) }));
+                    }
+                    for (j, call) in m.tool_calls.iter().flatten().enumerate() {
+                        // arguments are a JSON string, as in chat completions
+                        let arguments = match &call.function.arguments {
+                            Value::String(arguments) => arguments.clone(),
+                            arguments => arguments.to_string(),
+                        };
+                        items.push(json!({
+                            "type": "function_call",
+                            "call_id": ids.call(i, j, call),
+                            "name": call.function.name,
+                            "arguments": arguments,
+                        }));
+                    }
+                }
+            }
+        }
+        let instructions = (!instructions.is_empty()).then(|| instructions.join("\n\n"));
+        (instructions, items)
+    }
+
+    /// The Responses API only takes the output limit, `top_p` and `temperature`
+    fn apply_options(body: &mut Value, options: Option<&ModelOptions>) {
+        let Some(options) = options else {
+            return;
+        };
+        if let Some(num_predict) = options.num_predict
+            && num_predict > 0
+        {
+            body["max_output_tokens"] = json!(num_predict);
+        }
+        let mapped = [
+            ("top_p", options.top_p.map(|v| json!(v))),
+            ("temperature", options.temperature.map(|v| json!(v))),
+        ];
+        for (k, v) in mapped {
+            if let Some(v) = v {
+                body[k] = v;
+            }
+        }
+        let unmapped = [
+            ("stop", options.stop.is_some()),
+            ("seed", options.seed.is_some()),
+            ("presence_penalty", options.presence_penalty.is_some()),
+            ("frequency_penalty", options.frequency_penalty.is_some()),
+        ];
+        let extra = options.extra.keys().map(|key| (key.as_str(), true));
+        for (key, set) in unmapped.into_iter().chain(extra) {
+            if set {
+                warn!(
+                    "option '{}' has no Responses API equivalent, ignoring it",
+                    key
+                );
+            }
+        }
+    }
+
+    fn build_request_body(&self, request: &ChatCompletionRequest) -> Value {
+        let (instructions, input) = Self::build_input(&request.messages);
+        // the whole history is sent every time, nothing needs to be stored upstream
+        let mut body = json!({
+            "model": request.model,
+            "input": input,
+            "stream": true,
+            "store": false,
+        });
+        if let Some(instructions) = instructions {
+            body["instructions"] = json!(instructions);
+        }
+        if !request.tools.is_empty() {
+            // function tools lose their `function` wrapper, built-in tools (web_search, ...) are
+            // passed as they are
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| match tool.get("function") {
+                    Some(function) => {
+                        let mut tool = function.clone();
+                        tool["type"] = json!("function");
+                        tool
+                    }
+                    None => tool.clone(),
+                })
+                .collect();
+            body["tools"] = json!(tools);
+        }
+        match &request.format {
+            Some(Value::String(s)) if s == "json" => {
+                body["text"] = json!({ "format": { "type": "json_object" } });
+            }
+            Some(schema @ Value::Object(_)) => {
+                body["text"] = json!({
+                    "format": {
+                        "type": "json_schema",
+                        "name": "response",
+                        "schema": schema,
+                        "strict": true,
+                    },
+                });
+            }
+            Some(other) => warn!("unsupported format {}, ignoring it", other),
+            None => {}
+        }
+        // reasoning summaries are only sent when asked for
+        let effort = match &request.think {
+            Some(Think::Effort(effort)) => Some(effort.as_str()),
+            Some(Think::Enabled(true)) => Some("medium"),
+            Some(Think::Enabled(false)) | None => None,
+        };
+        if let Some(effort) = effort {
+            body["reasoning"] = json!({ "effort": effort, "summary": "auto" });
+        }
+
+        Self::apply_options(&mut body, request.options.as_ref());
+
+        body
+    }
+
+    fn build_request(
+        &self,
+        url: &str,
+        body: &Value,
+    ) -> Result<reqwest::RequestBuilder, ProviderError> {
+        let client = http_client()?;
+
+        let builder = client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", self.key))
+            .header("Content-Type", "application/json")
+            .json(body);
+
+        Ok(builder)
+    }
+
+    /// Sends a streaming request to /responses and reads its server-sent events
+    fn stream_request(
+        &self,
+        request_url: String,
+        body: &Value,
+    ) -> Result<ChatEventStream, ProviderError> {
+        let request = self.build_request(&request_url, body)?;
+
+        let stream = async_stream::stream! {
+            let response = match send(request, &request_url).await {
+                Ok(response) => response,
+                Err(e) => {
+                    yield ChatEvent::Error(e);
+                    return;
+                }
+            };
+            let mut lines = Box::pin(response_lines(response, request_url.clone()));
+            let mut done_reason = None;
+            // output item index -> tool call index
+            let mut tool_calls: HashMap<usize, usize> = HashMap::new();
+
+            // the event names are repeated in the data's `type`
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        yield ChatEvent::Error(e);
+                        return;
+                    }
+                };
+                let Some(data) = line.strip_prefix("data:") else {
+                    continue;
+                };
+                match serde_json::from_str::<StreamEvent>(data.trim()) {
+                    Ok(StreamEvent::OutputTextDelta { delta }) => yield ChatEvent::ContentDelta(delta),
+                    Ok(StreamEvent::RefusalDelta { delta }) => {
+                        done_reason = Some(StopReason::ContentFilter);
+                        yield ChatEvent::ContentDelta(delta);
+                    }
+                    Ok(StreamEvent::ReasoningDelta { delta }) => yield ChatEvent::ReasoningDelta(delta),
+                    Ok(StreamEvent::OutputItemAdded { output_index, item }) => {
+                        if item["type"] == "function_call" {
+                            let tool_index = tool_calls.len();
+                            tool_calls.insert(output_index, tool_index);
+                            yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                index: tool_index,
+                                id: item["call_id"].as_str().map(str::to_string),
+                                name: item["name"].as_str().map(str::to_string),
+                                arguments: item["arguments"].as_str().unwrap_or_default().to_string(),
+                            });
+                        }
+                    }
+                    Ok(StreamEvent::FunctionCallArgumentsDelta { output_index, delta }) => {
+                        if let Some(tool_index) = tool_calls.get(&output_index) {
+                            yield ChatEvent::ToolCallDelta(ToolCallDelta {
+                                index: *tool_index,
+                                arguments: delta,
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    Ok(StreamEvent::Finished { response }) => {
+                        if let Some(usage) = response.usage {
+                            yield ChatEvent::Usage(Metrics {
+                                prompt_eval_count: usage.input_tokens,
+                                eval_count: usage.output_tokens,
+                                ..Default::default()
+                            });
+                        }
+                        let reason = match response.incomplete_details {
+                            Some(details) if details.reason == "max_output_tokens" => StopReason::Length,
+                            Some(details) => StopReason::from_upstream(&details.reason),
+                            None if !tool_calls.is_empty() => StopReason::ToolCalls,
+                            None => StopReason::Stop,
+                        };
+                        yield ChatEvent::Done(Some(done_reason.unwrap_or(reason)));
+                        return;
+                    }
+                    Ok(StreamEvent::Failed { response }) => {
+                        let message = response
+                            .error
+                            .map(|e| format!("{}: {}", e.code, e.message))
+                            .unwrap_or_else(|| "response failed".to_string());
+                        yield ChatEvent::Error(ProviderError {
+                            kind: ProviderErrorKind::Status(500),
+                            message,
+                            request_url: Some(request_url.clone()),
+                        });
+                        return;
+                    }
+                    Ok(StreamEvent::Error { code, message }) => {
+                        yield ChatEvent::Error(ProviderError {
+                            kind: ProviderErrorKind::Status(500),
+                            message: format!("{}: {}", code.unwrap_or_default(), message),
+                            request_url: Some(request_url.clone()),
+                        });
+                        return;
+                    }
+                    Ok(StreamEvent::Other) => {}
+                    Err(e) => {
+                        yield ChatEvent::Error(ProviderError::decode(format!("JSON parse error: {}", e), &request_url));
+                        return;
+                    }
+                }
+            }
+
+            // the stream ended without response.completed
+            yield ChatEvent::Done(done_reason);
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for OpenAIResponsesProvider {
+    fn chat(&self, request: ChatCompletionRequest) -> Result<ChatEventStream, ProviderError> {
+        check_images(&self.models, &request.model, &request.messages)?;
+        let request_url = format!("{}/responses", self.base_url.trim_end_matches('/'));
+        let body = self.build_request_body(&request);
+        self.stream_request(request_url, &body)
+    }
+
+    async fn get_models(&self) -> Vec<Model> {
+        self.models.clone()
+    }
+
+    fn lists_models(&self) -> bool {
+        true
+    }
+
+    async fn fetch_models(&self) -> Result<Vec<Model>, ProviderError> {
+        let request_url = format!("{}/models", self.base_url.trim_end_matches('/'));
+        let request = http_client()?
+            .get(&request_url)
+            .header("Authorization", format!("Bearer {}", self.key));
+        let list: ModelList = send_json(request, &request_url).await?;
+        Ok(list.data.into_iter().map(|m| Model::named(m.id)).collect())
+    }
+}
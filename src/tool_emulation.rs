@@ -0,0 +1,254 @@
+use crate::models::{Message, ToolCall};
+use crate::providers::{
+    ChatCompletionRequest, ChatEvent, ChatEventStream, StopReason, ToolCallDelta,
+};
+use async_stream::stream;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+const OPEN: &str = "<tool_call>";
+const CLOSE: &str = "</tool_call>";
+
+/// Rewrites a request with tools for a model that does not support them: the tool schemas go
+/// into the system prompt, and earlier tool calls and results become plain text
+pub fn emulate_request(request: &mut ChatCompletionRequest) {
+    let tools = std::mem::take(&mut request.tools);
+    let definitions: Vec<&Value> = tools
+        .iter()
+        .map(|tool| tool.get("function").unwrap_or(tool))
+        .collect();
+    let instructions = format!(
+        "You can call the following tools. To call one, answer with a block in exactly this \
+         form, one block per call:\n{}\n{{\"name\": \"<tool name>\", \"arguments\": {{<arguments \
+         as a JSON object>}}}}\n{}\nTool results are sent back to you in messages starting with \
+         \"Tool result\". If no tool is needed, answer normally.\n\nTools:\n{}",
+        OPEN,
+        CLOSE,
+        serde_json::to_string_pretty(&definitions).unwrap_or_default()
+    );
+
+    let mut messages = Vec::with_capacity(request.messages.len() + 1);
+    for message in std::mem::take(&mut request.messages) {
+        match (message.role.as_str(), &message.tool_calls) {
+            ("assistant", Some(tool_calls)) => {
+                let mut content = message.content.clone();
+                for call in tool_calls {
+                    content.push_str(&format!("\n{}\n{}\n{}", OPEN, call_block(call), CLOSE));
+                }
+                messages.push(Message::new("assistant", content.trim_start().to_string()));
+            }
+            ("tool", _) => {
+                let content = match &message.tool_name {
+                    Some(name) => format!("Tool result ({}):\n{}", name, message.content),
+                    None => format!("Tool result:\n{}", message.content),
+                };
+                messages.push(Message::new("user", content));
+            }
+            _ => messages.push(message),
+        }
+    }
+    match messages.first_mut() {
+        Some(system) if system.role == "system" => {
+            system.content = format!("{}\n\n{}", system.content, instructions);
+        }
+        _ => messages.insert(0, Message::new("system", instructions)),
+    }
+    request.messages = messages;
+}
+
+fn call_block(call: &ToolCall) -> String {
+    serde_json::json!({ "name": call.function.name, "arguments": call.function.arguments })
+        .to_string()
+}
+
+// what the model writes between the tags
+#[derive(Deserialize)]
+struct EmulatedCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+/// Finds `<tool_call>` blocks in streamed content and turns them into tool call events, passing
+/// the text around them through
+#[derive(Default)]
+struct ToolCallParser {
+    buffer: String,
+    in_call: bool,
+    count: usize,
+}
+
+impl ToolCallParser {
+    fn push(&mut self, text: &str) -> Vec<ChatEvent> {
+        self.buffer.push_str(text);
+        let mut events = Vec::new();
+        loop {
+            if self.in_call {
+                let Some(end) = self.buffer.find(CLOSE) else {
+                    break;
+                };
+                let block = self.buffer[..end].to_string();
+                self.buffer.drain(..end + CLOSE.len());
+                self.in_call = false;
+                events.push(self.parse_call(&block));
+            } else if let Some(start) = self.buffer.find(OPEN) {
+                let text: String = self.buffer.drain(..start + OPEN.len()).collect();
+                push_content(&mut events, &text[..start]);
+                self.in_call = true;
+            } else {
+                // hold back what may be the start of a tag split across deltas
+                let keep = (1..OPEN.len())
+                    .rev()
+                    .find(|&k| self.buffer.ends_with(&OPEN[..k]))
+                    .unwrap_or(0);
+                let text: String = self.buffer.drain(..self.buffer.len() - keep).collect();
+                push_content(&mut events, &text);
+                break;
+            }
+        }
+        events
+    }
+
+    /// Flushes what is left at the end of the answer. An unclosed block is parsed as a call when
+    /// it holds one, models often stop before the closing tag
+    fn finish(&mut self) -> Vec<ChatEvent> {
+        let rest = std::mem::take(&mut self.buffer);
+        let mut events = Vec::new();
+        if self.in_call {
+            self.in_call = false;
+            events.push(self.parse_call(&rest));
+        } else {
+            push_content(&mut events, &rest);
+        }
+        events
+    }
+
+    fn parse_call(&mut self, block: &str) -> ChatEvent {
+        let json = block
+            .trim()
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```")
+            .trim();
+        match serde_json::from_str::<EmulatedCall>(json) {
+            Ok(call) => {
+                self.count += 1;
+                ChatEvent::ToolCallDelta(ToolCallDelta {
+                    index: self.count - 1,
+                    id: None,
+                    name: Some(call.name),
+                    arguments: call.arguments.to_string(),
+                })
+            }
+            Err(e) => {
+                warn!(
+                    "tool call block is not valid ({}), passing it as content",
+                    e
+                );
+                ChatEvent::ContentDelta(format!("{}{}{}", OPEN, block, CLOSE))
+            }
+        }
+    }
+}
+
+fn push_content(events: &mut Vec<ChatEvent>, text: &str) {
+    if !text.is_empty() {
+        events.push(ChatEvent::ContentDelta(text.to_string()));
+    }
+}
+
+/// Turns the `<tool_call>` blocks of an emulated answer back into tool calls
+pub fn parse_stream(stream: ChatEventStream) -> ChatEventStream {
+    Box::pin(stream! {
+        let mut parser = ToolCallParser::default();
+        let mut s = stream;
+        while let Some(event) = s.next().await {
+            match event {
+                ChatEvent::ContentDelta(content) => {
+                    for event in parser.push(&content) {
+                        yield event;
+                    }
+                }
+                ChatEvent::Done(reason) => {
+                    for event in parser.finish() {
+                        yield event;
+                    }
+                    if parser.count > 0 {
+                        yield ChatEvent::Done(Some(StopReason::ToolCalls));
+                    } else {
+                        yield ChatEvent::Done(reason);
+                    }
+                }
+                event => yield event,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `deltas` through a parser, returning the content passed through and the calls found
+    fn parse(deltas: &[&str]) -> (String, Vec<(String, Value)>) {
+        let mut parser = ToolCallParser::default();
+        let mut events: Vec<ChatEvent> = deltas.iter().flat_map(|d| parser.push(d)).collect();
+        events.extend(parser.finish());
+        let mut content = String::new();
+        let mut calls = Vec::new();
+        for event in events {
+            match event {
+                ChatEvent::ContentDelta(text) => content.push_str(&text),
+                ChatEvent::ToolCallDelta(call) => calls.push((
+                    call.name.unwrap(),
+                    serde_json::from_str(&call.arguments).unwrap(),
+                )),
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+        (content, calls)
+    }
+
+    #[test]
+    fn tags_split_across_deltas() {
+        let (content, calls) = parse(&[
+            "Checking.<tool",
+            "_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}</tool_",
+            "call> Done",
+        ]);
+        assert_eq!(content, "Checking. Done");
+        assert_eq!(
+            calls,
+            vec![(
+                "get_weather".to_string(),
+                serde_json::json!({"city": "Paris"})
+            )]
+        );
+    }
+
+    #[test]
+    fn held_back_text_that_is_no_tag_is_passed_on() {
+        let (content, calls) = parse(&["a < b <", "tool"]);
+        assert_eq!(content, "a < b <tool");
+        assert!(calls.is_empty());
+    }
+
+    #[test]
+    fn unclosed_final_block_is_a_call() {
+        let (content, calls) = parse(&[
+            "<tool_call>\n```json\n{\"name\": \"ls\", \"arguments\": {}}",
+            "\n```\n",
+        ]);
+        assert_eq!(content, "");
+        assert_eq!(calls, vec![("ls".to_string(), serde_json::json!({}))]);
+    }
+
+    #[test]
+    fn unclosed_final_block_without_a_call_is_content() {
+        let (content, calls) = parse(&["Sure <tool_call>not json"]);
+        assert_eq!(content, "Sure <tool_call>not json</tool_call>");
+        assert!(calls.is_empty());
+    }
+}